@@ -0,0 +1,269 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Security audit of the BMCs by their channel authentication capabilities, cipher suites, users and credentials
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::bmc_user::{self, User};
+use crate::sel::Severity;
+use crate::{lan, Format, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub struct AuditOpts {
+    /// The LAN channel
+    #[structopt(short, long, default_value = "1")]
+    channel: u8,
+    /// The rules to leave out, e.g. ipmi-1.5,unexpected-user
+    #[structopt(short, long, use_delimiter = true, parse(try_from_str = rule))]
+    ignore: Vec<String>,
+    /// Output format: table, json or csv
+    #[structopt(short, long, default_value = "table")]
+    format: Format,
+    #[structopt(flatten)]
+    group: Group,
+}
+
+/// The rules with the severity of their findings
+pub const RULES: &[(&str, Severity)] = &[
+    ("cipher-0",            Severity::Critical),
+    ("weak-cipher",         Severity::Warning),
+    ("anonymous",           Severity::Critical),
+    ("default-credentials", Severity::Critical),
+    ("cleartext-auth",      Severity::Critical),
+    ("ipmi-1.5",            Severity::Warning),
+    ("per-message-auth",    Severity::Warning),
+    ("unexpected-user",     Severity::Warning),
+];
+
+fn rule(s: &str) -> Result<String, String> {
+    RULES.iter().find(|(id, ..)| *id == s).map(|(id, ..)| id.to_string())
+        .ok_or(format!("invalid rule {}, expect one of {}", s, RULES.iter().map(|(id, ..)| *id).collect::<Vec<_>>().join(", ")))
+}
+
+/// The vendor default user names and passwords
+pub const DEFAULT_CREDENTIALS: &[(&str, &str)] = &[
+    ("ADMIN", "ADMIN"), ("root", "calvin"), ("USERID", "PASSW0RD"), ("admin", "admin"), ("root", "root"), ("Administrator", "superuser"),
+];
+
+/// The cipher suites of IPMI 2.0 table 22-20 without AES-CBC-128 encryption: none, xRC4-128 or xRC4-40
+const WEAK_CIPHERS: &[u8] = &[1, 2, 4, 5, 6, 7, 9, 10, 11, 13, 14, 15, 16, 18, 19];
+
+/// What is read from the BMC of a host to audit it
+#[derive(Debug, Default)]
+pub struct Probe {
+    /// `channel authcap <channel> 4`
+    pub authcap: Vec<(String, String)>,
+    /// `lan print <channel>`
+    pub lan: Vec<(String, String)>,
+    pub users: Vec<User>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub detail: String,
+    /// The commands to run on the host, or what to do otherwise
+    pub remediation: Vec<String>,
+}
+
+pub fn probe(host: &Login, channel: u8) -> Result<Probe, String> {
+    let ch = channel.to_string();
+    Ok(Probe {
+        authcap: lan::parse(&host.run(&["channel", "authcap", &ch, "4"])?),
+        lan: lan::parse(&host.run(&["lan", "print", &ch])?),
+        users: bmc_user::list(host, channel)?,
+    })
+}
+
+/// The enabled cipher suites, by the privilege of each suite ID, `X` if unused
+fn ciphers(lan: &[(String, String)]) -> Vec<u8> {
+    let privs: Vec<char> = lan::value(lan, "Cipher Suite Priv Max").unwrap_or_default().chars().collect();
+    lan::value(lan, "RMCP+ Cipher Suites").unwrap_or_default().split(',')
+        .filter_map(|id| id.trim().parse::<u8>().ok())
+        .filter(|id| privs.get(*id as usize).is_none_or(|p| *p != 'X'))
+        .collect()
+}
+
+/// The `lan set cipher_privs` argument to disable the cipher suites
+fn cipher_privs(lan: &[(String, String)], disable: &[u8]) -> String {
+    lan::value(lan, "Cipher Suite Priv Max").unwrap_or_default().chars().enumerate()
+        .map(|(i, p)| if disable.contains(&(i as u8)) { 'X' } else { p })
+        .collect()
+}
+
+/// Check the probe of the host against the rules
+pub fn findings(id: i64, host: &Host, probe: &Probe, channel: u8) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut find = |rule: &'static str, detail: String, remediation: Vec<String>| {
+        let severity = RULES.iter().find(|(r, ..)| *r == rule).map(|(_, s)| *s).unwrap();
+        findings.push(Finding {rule, severity, detail, remediation});
+    };
+    let authcap = |key| lan::value(&probe.authcap, key).unwrap_or_default();
+
+    let ciphers = ciphers(&probe.lan);
+    if ciphers.contains(&0) {
+        find("cipher-0", String::from("cipher suite 0 enabled, any password logs in"),
+            vec![format!("ipmi.exe -- lan set {} cipher_privs {}", channel, cipher_privs(&probe.lan, &[0]))]);
+    }
+    let weak: Vec<u8> = ciphers.iter().copied().filter(|c| WEAK_CIPHERS.contains(c)).collect();
+    if !weak.is_empty() {
+        find("weak-cipher", format!("cipher suites {} enabled", weak.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(",")),
+            vec![format!("ipmi.exe -- lan set {} cipher_privs {}", channel, cipher_privs(&probe.lan, &[&[0], &weak[..]].concat()))]);
+    }
+
+    let anonymous: Vec<&User> = probe.users.iter().filter(|u| u.active() && u.name.is_empty()).collect();
+    if authcap("Anonymous Login enabled") == "yes" || authcap("Null user names exist") == "yes" || !anonymous.is_empty() {
+        let mut remediation: Vec<String> = anonymous.iter().map(|u| format!("ipmi.exe bmc-user priv {} no_access", u.id)).collect();
+        remediation.push(String::from("ipmi.exe bmc-user disable 1"));
+        find("anonymous", format!("anonymous login {}, null user names {}", authcap("Anonymous Login enabled"), authcap("Null user names exist")), remediation);
+    }
+
+    if DEFAULT_CREDENTIALS.iter().any(|(user, pswd)| host.user == *user && host.pswd == *pswd) {
+        find("default-credentials", format!("{} has the vendor default password", host.user), vec![format!("ipmi.exe host rotate {} --generate", id)]);
+    }
+
+    let auth_types: Vec<&str> = authcap("IPMI v1.5  auth types").split_whitespace().collect();
+    let cleartext: Vec<&str> = auth_types.iter().copied().filter(|t| *t == "NONE" || *t == "PASSWORD").collect();
+    if !cleartext.is_empty() {
+        find("cleartext-auth", format!("authentication types {} enabled", cleartext.join(" ")),
+            vec![format!("ipmi.exe -- lan set {} auth ADMIN,OPERATOR,USER,CALLBACK MD5", channel)]);
+    }
+    if authcap("Channel supports IPMI v1.5") == "yes" && !auth_types.is_empty() {
+        find("ipmi-1.5", format!("authentication types {} enabled", auth_types.join(" ")),
+            vec![String::from("disable IPMI 1.5 (RMCP) in the network settings of the BMC, if it can be")]);
+    }
+    if authcap("Per message authentication") == "disabled" {
+        find("per-message-auth", String::from("per message authentication disabled"),
+            vec![String::from("enable per message authentication in the network settings of the BMC")]);
+    }
+
+    let unexpected: Vec<&User> = bmc_user::unexpected(&probe.users, &[], host).into_iter().filter(|u| !u.name.is_empty()).collect();
+    if !unexpected.is_empty() {
+        find("unexpected-user", format!("users {} enabled", unexpected.iter().map(|u| u.name.as_str()).collect::<Vec<_>>().join(", ")),
+            unexpected.iter().map(|u| format!("ipmi.exe bmc-user disable {}", u.name)).collect());
+    }
+    findings
+}
+
+/// 100 less 30 for each critical finding and 10 for each warning
+pub fn score(findings: &[Finding]) -> u32 {
+    let penalty: u32 = findings.iter().map(|f| match f.severity { Severity::Critical => 30, Severity::Warning => 10, Severity::Info => 0 }).sum();
+    100u32.saturating_sub(penalty)
+}
+
+type Report = (i64, Login, Result<Vec<Finding>, String>);
+
+fn print(reports: &[Report], format: Format) {
+    match format {
+        Format::Table => {
+            for (id, host, findings) in reports {
+                match findings {
+                    Ok(findings) => {
+                        println!("{:>04} {:<15} score {}", id, host.ip, score(findings));
+                        if !findings.is_empty() {
+                            println!("    ipmi.exe host use {}", id);
+                        }
+                        for f in findings {
+                            println!("    {:<8}  {:<19}  {}", f.severity, f.rule, f.detail);
+                            for r in &f.remediation {
+                                println!("    {:<8}  {:<19}  {}", "", "", r);
+                            }
+                        }
+                    },
+                    Err(e) => println!("{:>04} {:<15} failed: {}", id, host.ip, e),
+                }
+            }
+        },
+        Format::Json => {
+            #[derive(Serialize)]
+            struct Entry<'a> {
+                host: &'a str,
+                score: Option<u32>,
+                findings: &'a [Finding],
+                error: Option<&'a str>,
+            }
+            let entries: Vec<Entry> = reports.iter().map(|(_, host, findings)| match findings {
+                Ok(findings) => Entry {host: &host.ip, score: Some(score(findings)), findings, error: None},
+                Err(e) => Entry {host: &host.ip, score: None, findings: &[], error: Some(e)},
+            }).collect();
+            println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+        },
+        Format::Csv => {
+            println!("host,score,rule,severity,detail,remediation");
+            for (_, host, findings) in reports {
+                if let Ok(findings) = findings {
+                    for f in findings {
+                        println!("{},{},{},{},{},{}", host.ip, score(findings), f.rule, f.severity, crate::csv(&f.detail), crate::csv(&f.remediation.join("; ")));
+                    }
+                }
+            }
+        },
+    }
+}
+
+pub fn run(connection: &sqlite::Connection, opts: AuditOpts) {
+    let hosts = Host::group_or_help(connection, opts.group.tag.as_deref());
+    if hosts.is_empty() {
+        return;
+    }
+
+    let channel = opts.channel;
+    let reports: Vec<Report> = crate::parallel(hosts, opts.group.jobs, |(id, host)| {
+        let findings = probe(&host, channel).map(|probe| {
+            findings(id, &host, &probe, channel).into_iter().filter(|f| !opts.ignore.iter().any(|r| r == f.rule)).collect()
+        });
+        (id, host, findings)
+    });
+    print(&reports, opts.format);
+
+    if opts.format == Format::Table && reports.len() > 1 {
+        let audited: Vec<u32> = reports.iter().filter_map(|(.., f)| f.as_ref().ok()).map(|f| score(f)).collect();
+        println!();
+        println!("{} host(s) audited, {} failed, {} with critical findings, {} with a score of 100",
+            audited.len(), reports.len() - audited.len(),
+            reports.iter().filter(|(.., f)| f.as_ref().is_ok_and(|f| f.iter().any(|f| f.severity == Severity::Critical))).count(),
+            audited.iter().filter(|s| **s == 100).count());
+    }
+}
+
+#[test]
+fn audit_findings() {
+    let read = |file: &str| std::fs::read_to_string(format!("tests/fixtures/{}", file)).unwrap();
+    let probe = Probe {
+        authcap: lan::parse(&read("authcap_supermicro.txt")),
+        lan: lan::parse(&read("lan_print_supermicro.txt")),
+        users: bmc_user::parse(&read("user_list_supermicro.txt")),
+    };
+    let host = Host {ip: String::from("10.245.38.1"), user: String::from("ADMIN"), pswd: String::from("ADMIN")};
+    let found = findings(1, &host, &probe, 1);
+    assert_eq!(found.iter().map(|f| f.rule).collect::<Vec<_>>(), vec!["weak-cipher", "default-credentials", "cleartext-auth", "ipmi-1.5", "unexpected-user"]);
+    assert_eq!(found[0].detail, "cipher suites 1,2,6,7,11 enabled");
+    assert_eq!(found[0].remediation, vec!["ipmi.exe -- lan set 1 cipher_privs XXXaXXXXaXXXaXX"]);
+    assert_eq!(found[1].remediation, vec!["ipmi.exe host rotate 1 --generate"]);
+    assert_eq!(found[4].remediation, vec!["ipmi.exe bmc-user disable monitor"]);
+    assert_eq!(score(&found), 10);
+
+    let probe = Probe {
+        authcap: lan::parse(&read("authcap_dell.txt")),
+        lan: lan::parse(&read("lan_print_dell.txt")),
+        users: bmc_user::parse(&read("user_list_dell.txt")),
+    };
+    let host = Host {ip: String::from("10.245.38.2"), user: String::from("root"), pswd: String::from("Rack12-root-2020")};
+    let found = findings(2, &host, &probe, 1);
+    assert_eq!(found.iter().map(|f| f.rule).collect::<Vec<_>>(), vec!["cipher-0", "weak-cipher", "anonymous", "per-message-auth", "unexpected-user"]);
+    assert_eq!(found[0].remediation, vec!["ipmi.exe -- lan set 1 cipher_privs Xaaaaaaaaaaaaaa"]);
+    assert_eq!(found[2].remediation, vec!["ipmi.exe bmc-user disable 1"]);
+    assert_eq!(score(&found), 10);
+    assert_eq!(score(&found[..2]), 60);
+}
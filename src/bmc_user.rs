@@ -0,0 +1,337 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! BMC local users of a channel, see IPMI 2.0 section 22.26 Get User Access and 22.28 Set User Name
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::{Format, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub enum BmcUserCommand {
+    /// List the users of the host(s), the named ones other than expected are marked unexpected
+    List {
+        /// The names of the users expected on the BMCs, the user of each host by default
+        #[structopt(short, long, use_delimiter = true)]
+        expect: Vec<String>,
+        /// Only list the hosts with unexpected users, and the users
+        #[structopt(short, long)]
+        unexpected: bool,
+        #[structopt(flatten)]
+        opts: UserOpts,
+    },
+    /// Add the user to the first free user ID of the host(s), or set it again where it is there
+    Add {
+        /// The name of the user, at most 16 bytes
+        name: String,
+        /// The password of the user, at most 20 bytes
+        #[structopt(short, long)]
+        password: String,
+        /// The privilege of the user: callback, user, operator, administrator or oem
+        #[structopt(long, default_value = "administrator", parse(try_from_str = privilege))]
+        privilege: u8,
+        #[structopt(flatten)]
+        opts: UserOpts,
+    },
+    /// Set the password of the user, by name or user ID
+    Passwd {
+        user: String,
+        /// The new password of the user, at most 20 bytes
+        #[structopt(short, long)]
+        password: String,
+        #[structopt(flatten)]
+        opts: UserOpts,
+    },
+    /// Enable the user, by name or user ID
+    Enable {
+        user: String,
+        #[structopt(flatten)]
+        opts: UserOpts,
+    },
+    /// Disable the user, by name or user ID
+    Disable {
+        user: String,
+        #[structopt(flatten)]
+        opts: UserOpts,
+    },
+    /// Set the channel privilege of the user, by name or user ID
+    Priv {
+        user: String,
+        /// callback, user, operator, administrator, oem or no_access
+        #[structopt(parse(try_from_str = privilege))]
+        privilege: u8,
+        #[structopt(flatten)]
+        opts: UserOpts,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub struct UserOpts {
+    /// The LAN channel
+    #[structopt(short, long, default_value = "1")]
+    channel: u8,
+    /// Output format: table, json or csv
+    #[structopt(short, long, default_value = "table")]
+    format: Format,
+    #[structopt(flatten)]
+    group: Group,
+}
+
+/// A row of `user list`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct User {
+    pub id: u8,
+    pub name: String,
+    pub callin: bool,
+    pub link_auth: bool,
+    pub ipmi_msg: bool,
+    /// The channel privilege limit, e.g. ADMINISTRATOR or NO ACCESS
+    pub privilege: String,
+}
+
+impl User {
+    /// A user able to log in over the channel
+    pub fn active(&self) -> bool {
+        !self.name.is_empty() || (self.privilege != "NO ACCESS" && !self.privilege.starts_with("Unknown"))
+    }
+}
+
+const PRIVILEGES: &[(&str, u8)] = &[
+    ("callback", 1), ("user", 2), ("operator", 3), ("administrator", 4), ("oem", 5), ("no_access", 15),
+];
+
+fn privilege(s: &str) -> Result<u8, String> {
+    let s = s.to_lowercase().replace([' ', '-'], "_");
+    PRIVILEGES.iter().find(|(name, _)| *name == s || (s.len() >= 3 && name.starts_with(&s))).map(|(_, level)| *level)
+        .ok_or(format!("invalid privilege {}, expect one of callback, user, operator, administrator, oem or no_access", s))
+}
+
+/// Parse the `user list` table, the columns are of fixed width
+pub fn parse(output: &str) -> Vec<User> {
+    let column = |line: &str, from: usize, to: Option<usize>| {
+        let to = to.unwrap_or(line.len()).min(line.len());
+        line.get(from.min(to)..to).unwrap_or_default().trim().to_string()
+    };
+    output.lines().filter_map(|line| {
+        let id = column(line, 0, Some(4)).parse::<u8>().ok()?;
+        Some(User {
+            id,
+            name: column(line, 4, Some(21)),
+            callin: column(line, 21, Some(29)) == "true",
+            link_auth: column(line, 29, Some(40)) == "true",
+            ipmi_msg: column(line, 40, Some(51)) == "true",
+            privilege: column(line, 51, None),
+        })
+    }).collect()
+}
+
+/// The active users not expected, with the user of the host expected if none are given
+pub fn unexpected<'a>(users: &'a [User], expect: &[String], host: &Host) -> Vec<&'a User> {
+    users.iter().filter(|u| u.active() && !if expect.is_empty() { u.name == host.user } else { expect.contains(&u.name) }).collect()
+}
+
+pub fn list(host: &Login, channel: u8) -> Result<Vec<User>, String> {
+    let users = parse(&host.run(&["user", "list", &channel.to_string()])?);
+    if users.is_empty() {
+        return Err(String::from("no users listed"));
+    }
+    Ok(users)
+}
+
+/// The user ID of the user by name, or the user ID itself
+fn find(users: &[User], user: &str) -> Result<u8, String> {
+    users.iter().find(|u| !u.name.is_empty() && u.name == user).map(|u| u.id)
+        .or_else(|| user.parse::<u8>().ok().filter(|id| users.iter().any(|u| u.id == *id)))
+        .ok_or(format!("no user {}", user))
+}
+
+/// The characters of the passwords not kept as they are by the shell in double quotes
+pub const UNQUOTABLE: &[char] = &['"', '\\', '$', '`', '%', '\n'];
+
+/// The passwords are given to ipmitool through the shell, quoted the same as the password to log in with
+fn quote(password: &str) -> Result<String, String> {
+    if password.is_empty() || password.len() > 20 {
+        return Err(String::from("the password must be 1 to 20 bytes"));
+    }
+    if password.contains(UNQUOTABLE) {
+        return Err(String::from("the password must not contain \", \\, $, `, % or a newline"));
+    }
+    Ok(crate::quote(password))
+}
+
+/// The user names are given to ipmitool through the shell quoted the same, up to the 16 bytes IPMI keeps of a name
+fn quote_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.len() > 16 {
+        return Err(String::from("the user name must be 1 to 16 bytes"));
+    }
+    if name.contains(UNQUOTABLE) {
+        return Err(String::from("the user name must not contain \", \\, $, `, % or a newline"));
+    }
+    Ok(crate::quote(name))
+}
+
+/// Set the password of the user ID, with a 20 byte password if longer than 16 bytes
+pub fn set_password(host: &Login, id: u8, password: &str) -> Result<(), String> {
+    let quoted = quote(password)?;
+    let id = id.to_string();
+    let mut args = vec!["user", "set", "password", &id, &quoted];
+    if password.len() > 16 {
+        args.push("20");
+    }
+    host.run(&args).map(|_| ())
+}
+
+fn add(host: &Login, channel: u8, name: &str, password: &str, privilege: u8) -> Result<u8, String> {
+    let quoted = quote_name(name)?;
+    let users = list(host, channel)?;
+    // user ID 1 is the anonymous user
+    let id = find(&users, name).ok()
+        .or_else(|| users.iter().find(|u| u.id > 1 && u.name.is_empty()).map(|u| u.id))
+        .ok_or("no free user ID")?;
+    let (ch, uid) = (channel.to_string(), id.to_string());
+    host.run(&["user", "set", "name", &uid, &quoted])?;
+    set_password(host, id, password)?;
+    host.run(&["channel", "setaccess", &ch, &uid, "callin=on", "ipmi=on", "link=on", &format!("privilege={}", privilege)])?;
+    host.run(&["user", "enable", &uid])?;
+    Ok(id)
+}
+
+/// Print the users, each with the host it is from and if it is unexpected
+fn print(users: &[(i64, &Login, &User, bool)], format: Format) {
+    match format {
+        Format::Table => {
+            println!("{:<4} {:<15} {:>3}  {:<16}  {:<6}  {:<9}  {:<8}  {:<13}", "Host", "IP", "Id", "Name", "Callin", "Link Auth", "IPMI Msg", "Privilege");
+            for (id, host, u, unexpected) in users {
+                println!(
+                    "{:>04} {:<15} {:>3}  {:<16}  {:<6}  {:<9}  {:<8}  {:<13}{}",
+                    id, host.ip, u.id, u.name, u.callin, u.link_auth, u.ipmi_msg, u.privilege, if *unexpected { "  unexpected" } else { "" },
+                );
+            }
+        },
+        Format::Json => {
+            #[derive(Serialize)]
+            struct Entry<'a> {
+                host: &'a str,
+                #[serde(flatten)]
+                user: &'a User,
+                unexpected: bool,
+            }
+            let entries: Vec<Entry> = users.iter().map(|(_, host, user, unexpected)| Entry {host: &host.ip, user, unexpected: *unexpected}).collect();
+            println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+        },
+        Format::Csv => {
+            println!("host,id,name,callin,link_auth,ipmi_msg,privilege,unexpected");
+            for (_, host, u, unexpected) in users {
+                println!("{},{},{},{},{},{},{},{}", host.ip, u.id, crate::csv(&u.name), u.callin, u.link_auth, u.ipmi_msg, u.privilege, unexpected);
+            }
+        },
+    }
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: BmcUserCommand) {
+    let opts = match &cmd {
+        BmcUserCommand::List{opts, ..} | BmcUserCommand::Add{opts, ..} | BmcUserCommand::Passwd{opts, ..}
+            | BmcUserCommand::Enable{opts, ..} | BmcUserCommand::Disable{opts, ..} | BmcUserCommand::Priv{opts, ..} => opts,
+    };
+    let hosts = Host::group_or_help(connection, opts.group.tag.as_deref());
+    if hosts.is_empty() {
+        return;
+    }
+    let (channel, format) = (opts.channel, opts.format);
+
+    if let BmcUserCommand::List{expect, unexpected: only_unexpected, opts} = &cmd {
+        let results = crate::parallel(hosts, opts.group.jobs, |(id, host)| {
+            let users = list(&host, channel);
+            (id, host, users)
+        });
+        let mut rows = Vec::new();
+        let mut reported = Vec::new();
+        for (id, host, users) in &results {
+            match users {
+                Ok(users) => {
+                    let unexpected = unexpected(users, expect, host);
+                    if !unexpected.is_empty() {
+                        reported.push((id, host, unexpected.iter().map(|u| if u.name.is_empty() { format!("#{}", u.id) } else { u.name.clone() }).collect::<Vec<_>>()));
+                    }
+                    rows.extend(users.iter()
+                        .filter(|u| u.active())
+                        .map(|u| (*id, host, u, unexpected.iter().any(|x| x.id == u.id)))
+                        .filter(|(.., unexpected)| *unexpected || !only_unexpected));
+                },
+                Err(e) => eprintln!("{:>04} {:<15} failed: {}", id, host.ip, e),
+            }
+        }
+        print(&rows, format);
+        if format == Format::Table && !reported.is_empty() {
+            println!();
+            println!("{} host(s) with unexpected users:", reported.len());
+            for (id, host, names) in &reported {
+                println!("{:>04} {:<15} {}", id, host.ip, names.join(", "));
+            }
+        }
+        return;
+    }
+
+    let results = crate::parallel(hosts, opts.group.jobs, |(id, host)| {
+        let result = match &cmd {
+            BmcUserCommand::Add{name, password, privilege, ..} => add(&host, channel, name, password, *privilege).map(|uid| format!("{} added as user ID {}", name, uid)),
+            BmcUserCommand::Passwd{user, password, ..} => list(&host, channel).and_then(|users| find(&users, user))
+                .and_then(|uid| set_password(&host, uid, password)).map(|_| format!("password of {} set", user)),
+            BmcUserCommand::Enable{user, ..} => list(&host, channel).and_then(|users| find(&users, user))
+                .and_then(|uid| host.run(&["user", "enable", &uid.to_string()])).map(|_| format!("{} enabled", user)),
+            BmcUserCommand::Disable{user, ..} => list(&host, channel).and_then(|users| find(&users, user))
+                .and_then(|uid| host.run(&["user", "disable", &uid.to_string()])).map(|_| format!("{} disabled", user)),
+            BmcUserCommand::Priv{user, privilege, ..} => list(&host, channel).and_then(|users| find(&users, user))
+                .and_then(|uid| host.run(&["user", "priv", &uid.to_string(), &privilege.to_string(), &channel.to_string()]))
+                .map(|_| format!("privilege of {} set to {}", user, privilege)),
+            BmcUserCommand::List{..} => unreachable!(),
+        };
+        (id, host, result)
+    });
+    for (id, host, result) in &results {
+        match result {
+            Ok(done) => println!("{:>04} {:<15} {}", id, host.ip, done),
+            Err(e) => println!("{:>04} {:<15} failed: {}", id, host.ip, e),
+        }
+    }
+}
+
+#[test]
+fn bmc_user_parse() {
+    let users = parse(&std::fs::read_to_string("tests/fixtures/user_list_supermicro.txt").unwrap());
+    assert_eq!(users.len(), 5);
+    assert_eq!(users[1], User {
+        id: 2, name: String::from("ADMIN"), callin: false, link_auth: false, ipmi_msg: true, privilege: String::from("ADMINISTRATOR"),
+    });
+    assert_eq!(users.iter().filter(|u| u.active()).map(|u| u.id).collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(find(&users, "monitor"), Ok(3));
+    assert_eq!(find(&users, "4"), Ok(4));
+    assert!(find(&users, "root").is_err());
+
+    let host = Host {ip: String::from("10.245.38.1"), user: String::from("ADMIN"), pswd: String::from("ADMIN")};
+    assert_eq!(unexpected(&users, &[], &host).iter().map(|u| u.id).collect::<Vec<_>>(), vec![3]);
+    assert!(unexpected(&users, &[String::from("ADMIN"), String::from("monitor")], &host).is_empty());
+
+    let users = parse(&std::fs::read_to_string("tests/fixtures/user_list_dell.txt").unwrap());
+    assert_eq!((users[2].name.as_str(), users[2].privilege.as_str()), ("calvin-backup", "OPERATOR"));
+    assert!(!users[0].active());
+
+    assert_eq!(privilege("Administrator"), Ok(4));
+    assert_eq!(privilege("no access"), Ok(15));
+    assert!(privilege("root").is_err());
+    assert!(quote("a\"b").is_err());
+    assert!(quote(&"x".repeat(21)).is_err());
+    assert_eq!(quote_name("ops-01"), Ok(String::from("ops-01")));
+    assert!(quote_name("ops;reboot").is_ok_and(|q| q != "ops;reboot"));
+    assert!(quote_name("$(reboot)").is_err());
+    assert!(quote_name(&"x".repeat(17)).is_err());
+}
@@ -0,0 +1,187 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Reachability of the BMCs by RMCP presence ping, kept in the `checks` table for `host list`
+
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+use crate::{rmcp, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub struct CheckOpts {
+    /// Check all hosts instead of the current host or the hosts with the tag
+    #[structopt(short, long, conflicts_with = "tag")]
+    all: bool,
+    /// Get the channel authentication capabilities of the BMCs that answer, too
+    #[structopt(long)]
+    auth: bool,
+    /// The time to wait for an answer, tried twice, in milliseconds
+    #[structopt(long, default_value = "1000")]
+    timeout: u64,
+    #[structopt(flatten)]
+    group: Group,
+}
+
+/// The status of a host as shown by `host list`, e.g. up 0.8ms, empty if never checked
+pub const STATUS: &str = "IFNULL((SELECT CASE status WHEN 'up' THEN printf('up %.1fms', latency) ELSE status END FROM checks WHERE host_id=hosts.id), '')";
+
+#[derive(Debug, PartialEq)]
+struct Check {
+    /// up, no-ipmi if the BMC answers for ASF only, or down
+    status: &'static str,
+    latency: Option<Duration>,
+    auth: Option<Result<rmcp::AuthCaps, String>>,
+    error: Option<String>,
+}
+
+fn check(host: &Login, auth: bool, timeout: Duration) -> Check {
+    match rmcp::ping(&host.ip, timeout) {
+        Ok(rmcp::Pong {ipmi: true, latency}) => Check {
+            status: "up", latency: Some(latency), auth: if auth { Some(rmcp::auth_caps(&host.ip, timeout)) } else { None }, error: None,
+        },
+        Ok(rmcp::Pong {ipmi: false, latency}) => Check {status: "no-ipmi", latency: Some(latency), auth: None, error: None},
+        Err(e) => Check {status: "down", latency: None, auth: None, error: Some(e)},
+    }
+}
+
+/// The IPMI versions the BMC supports, e.g. 1.5/2.0
+pub fn version(caps: &rmcp::AuthCaps) -> &'static str {
+    match (caps.ipmi15, caps.ipmi20) {
+        (true, true) => "1.5/2.0",
+        (false, true) => "2.0",
+        _ => "1.5",
+    }
+}
+
+/// The authentication types, and if anonymous login is on, e.g. MD5 PASSWORD, anonymous
+pub fn auth(caps: &rmcp::AuthCaps) -> String {
+    let mut auth = caps.auth_types.join(" ");
+    if caps.anonymous || caps.null_users {
+        auth.push_str(", anonymous");
+    }
+    auth
+}
+
+/// A short summary of the authentication capabilities, e.g. IPMI 1.5/2.0, MD5 PASSWORD
+fn summary(caps: &rmcp::AuthCaps) -> String {
+    format!("IPMI {}, {}", version(caps), auth(caps))
+}
+
+/// Keep the status of the host, and when it answered last
+fn store(connection: &sqlite::Connection, host_id: i64, status: &str, latency: Option<Duration>, time: i64) {
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO checks (host_id, status, latency, checked, seen)
+         VALUES (?1, ?2, ?3, ?4, CASE WHEN ?2='down' THEN (SELECT seen FROM checks WHERE host_id=?1) ELSE ?4 END)"
+    ).unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.bind(2, status).unwrap();
+    match latency {
+        Some(latency) => statement.bind(3, latency.as_secs_f64() * 1000.0).unwrap(),
+        None => statement.bind(3, ()).unwrap(),
+    }
+    statement.bind(4, time).unwrap();
+    statement.next().unwrap();
+}
+
+/// When the host answered last, if ever
+fn seen(connection: &sqlite::Connection, host_id: i64) -> Option<i64> {
+    let mut statement = connection.prepare("SELECT seen FROM checks WHERE host_id=?").unwrap();
+    statement.bind(1, host_id).unwrap();
+    match statement.next().unwrap() {
+        sqlite::State::Row => statement.read::<Option<i64>>(0).unwrap(),
+        sqlite::State::Done => None,
+    }
+}
+
+pub fn run(connection: &sqlite::Connection, opts: CheckOpts) {
+    let hosts = if opts.all { Host::all(connection) } else { Host::group_or_help(connection, opts.group.tag.as_deref()) };
+    let (auth, timeout) = (opts.auth, Duration::from_millis(opts.timeout));
+    let results = crate::parallel(hosts, opts.group.jobs, |(id, host)| {
+        let check = check(&host, auth, timeout);
+        (id, host, check)
+    });
+
+    let time = crate::now();
+    let mut up = 0;
+    for (id, host, check) in &results {
+        store(connection, *id, check.status, check.latency, time);
+        let mut line = match check.latency {
+            Some(latency) => format!("{:>04} {:<15} {} {:.1}ms", id, host.ip, check.status, latency.as_secs_f64() * 1000.0),
+            None => format!("{:>04} {:<15} {}", id, host.ip, check.status),
+        };
+        match (&check.auth, &check.error) {
+            (Some(Ok(caps)), _) => line.push_str(&format!(", {}", summary(caps))),
+            (Some(Err(e)), _) => line.push_str(&format!(", failed to get the authentication capabilities: {}", e)),
+            (None, Some(e)) => line.push_str(&format!(": {}", e)),
+            (None, None) => {},
+        }
+        if check.status == "down" {
+            if let Some(seen) = seen(connection, *id) {
+                line.push_str(&format!(", last seen {}", crate::datetime(seen)));
+            }
+        } else {
+            up += 1;
+        }
+        println!("{}", line);
+    }
+    if results.len() > 1 {
+        println!("{} of {} host(s) answered", up, results.len());
+    }
+}
+
+#[test]
+fn check_store() {
+    let db_name = "check.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database
+    };
+    std::fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '10.245.38.1', 'admin', 'admin')").unwrap();
+        connection.execute("INSERT INTO hosts VALUES (2, 0, '10.245.38.2', 'admin', 'admin')").unwrap();
+        let status = |id: i64| {
+            let mut statement = connection.prepare(format!("SELECT {} FROM hosts WHERE id={}", STATUS, id)).unwrap();
+            statement.next().unwrap();
+            statement.read::<String>(0).unwrap()
+        };
+        assert_eq!(status(1), "");
+
+        store(&connection, 1, "up", Some(Duration::from_micros(830)), 1000);
+        assert_eq!(status(1), "up 0.8ms");
+        assert_eq!(seen(&connection, 1), Some(1000));
+
+        store(&connection, 1, "down", None, 2000);
+        assert_eq!(status(1), "down");
+        assert_eq!(seen(&connection, 1), Some(1000));
+
+        store(&connection, 2, "down", None, 2000);
+        assert_eq!(seen(&connection, 2), None);
+        store(&connection, 2, "no-ipmi", Some(Duration::from_millis(3)), 3000);
+        assert_eq!(status(2), "no-ipmi");
+        assert_eq!(seen(&connection, 2), Some(3000));
+    }
+    std::fs::remove_file(&database).unwrap();
+}
+
+#[test]
+fn check_summary() {
+    let caps = rmcp::AuthCaps {
+        channel: 1, auth_types: vec!["MD5", "PASSWORD"], ipmi15: true, ipmi20: true,
+        per_message_auth: true, user_level_auth: true, non_null_users: true, null_users: false, anonymous: true,
+    };
+    assert_eq!(summary(&caps), "IPMI 1.5/2.0, MD5 PASSWORD, anonymous");
+}
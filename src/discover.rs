@@ -0,0 +1,237 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Discovery of the BMCs in an address range by RMCP presence ping, to add them as hosts
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+use crate::{check, mc, profile, rmcp, Host};
+
+/// The max number of addresses of a range, a /16
+const MAX_ADDRESSES: u32 = 65536;
+
+#[derive(StructOpt, Debug)]
+pub struct DiscoverOpts {
+    /// The network to scan, e.g. 10.245.38.0/24, or a range of addresses, e.g. 10.245.38.1-10.245.38.40
+    network: String,
+    /// The host numbers in the network to scan only, e.g. 1-40
+    #[structopt(short, long, parse(try_from_str = offsets))]
+    range: Option<(u32, u32)>,
+    /// The user name to add the BMCs found with
+    #[structopt(short, long, requires = "pswd", conflicts_with = "probe-defaults")]
+    user: Option<String>,
+    /// The password to add the BMCs found with
+    #[structopt(short, long, requires = "user")]
+    pswd: Option<String>,
+    /// Add the BMCs found with the first of the default credentials (see `host defaults`) that logs in
+    #[structopt(long)]
+    probe_defaults: bool,
+    /// Add the BMCs found with the credential profile, see `host profile`
+    #[structopt(long, conflicts_with_all = &["user", "probe-defaults"])]
+    profile: Option<String>,
+    /// The time to wait between the default credentials tried, e.g. 5s
+    #[structopt(long, default_value = "5s", parse(try_from_str = crate::seconds))]
+    delay: u64,
+    /// Tag the hosts added
+    #[structopt(short, long)]
+    tag: Option<String>,
+    /// Add the BMCs found without asking for confirmation
+    #[structopt(short, long)]
+    yes: bool,
+    /// The time to wait for an answer, tried twice, in milliseconds
+    #[structopt(long, default_value = "500")]
+    timeout: u64,
+    /// Max number of addresses to ping in parallel
+    #[structopt(short, long, default_value = "64")]
+    jobs: usize,
+}
+
+fn ipv4(s: &str) -> Result<Ipv4Addr, String> {
+    match rmcp::addr(s.trim()) {
+        Ok(IpAddr::V4(ip)) => Ok(ip),
+        _ => Err(format!("invalid IPv4 address {}", s)),
+    }
+}
+
+/// The first address and the prefix length of a network, e.g. 10.245.38.0/24
+fn network(s: &str) -> Result<(u32, u32), String> {
+    let (ip, prefix) = s.split_once('/').ok_or(format!("invalid network {}, expect e.g. 10.245.38.0/24", s))?;
+    let prefix = prefix.parse::<u32>().ok().filter(|p| *p <= 32).ok_or(format!("invalid prefix length of {}", s))?;
+    if 32 - prefix > MAX_ADDRESSES.trailing_zeros() {
+        return Err(format!("too many addresses in {}, at most a /16", s));
+    }
+    let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix) };
+    Ok((u32::from(ipv4(ip)?) & mask, prefix))
+}
+
+/// The addresses of a network without its network and broadcast addresses, of an inclusive range, or an address
+pub fn addresses(s: &str) -> Result<Vec<Ipv4Addr>, String> {
+    let (first, last) = if let Some((first, last)) = s.split_once('-') {
+        let (first, last) = (u32::from(ipv4(first)?), u32::from(ipv4(last)?));
+        if first > last {
+            return Err(format!("invalid range {}, the first address is after the last", s));
+        }
+        if last - first >= MAX_ADDRESSES {
+            return Err(format!("too many addresses in {}, at most {}", s, MAX_ADDRESSES));
+        }
+        (first, last)
+    } else if s.contains('/') {
+        let (base, prefix) = network(s)?;
+        let last = base | u32::MAX.checked_shr(prefix).unwrap_or(0);
+        if prefix <= 30 { (base + 1, last - 1) } else { (base, last) }
+    } else {
+        let ip = u32::from(ipv4(s)?);
+        (ip, ip)
+    };
+    Ok((first..=last).map(Ipv4Addr::from).collect())
+}
+
+/// An inclusive range of host numbers, e.g. 1-40
+fn offsets(s: &str) -> Result<(u32, u32), String> {
+    let range = s.split_once('-').and_then(|(a, b)| Some((a.trim().parse::<u32>().ok()?, b.trim().parse::<u32>().ok()?)));
+    range.filter(|(a, b)| a <= b).ok_or(format!("invalid range {}, expect e.g. 1-40", s))
+}
+
+/// The hosts by address, to leave out the BMCs added with the same user already
+fn known(connection: &sqlite::Connection) -> Vec<(Ipv4Addr, i64, String)> {
+    Host::all(connection).into_iter().filter_map(|(id, host)| match rmcp::addr(&host.ip) {
+        Ok(IpAddr::V4(ip)) => Some((ip, id, host.host.user)),
+        _ => None,
+    }).collect()
+}
+
+pub fn run(connection: &sqlite::Connection, opts: DiscoverOpts) {
+    let targets = match (addresses(&opts.network), opts.range) {
+        (Ok(targets), None) => targets,
+        // the host numbers count from the network address
+        (Ok(targets), Some((a, b))) => match network(&opts.network) {
+            Ok((base, _)) => targets.into_iter().filter(|ip| (a..=b).contains(&(u32::from(*ip) - base))).collect(),
+            Err(e) => {
+                println!("{}, --range takes a network", e);
+                return;
+            },
+        },
+        (Err(e), _) => {
+            println!("{}", e);
+            return;
+        },
+    };
+    if targets.is_empty() {
+        println!("No addresses to scan");
+        return;
+    }
+    let profile = opts.profile.as_deref().map(|name| profile::get(connection, name));
+    if let Some(None) = profile {
+        println!("Please list and find available profiles:");
+        println!("    ipmi.exe host profile list");
+        return;
+    }
+    println!("Pinging {} address(es) {} - {}", targets.len(), targets[0], targets[targets.len() - 1]);
+
+    let timeout = Duration::from_millis(opts.timeout);
+    let found: Vec<(Ipv4Addr, Result<rmcp::AuthCaps, String>)> = crate::parallel(targets, opts.jobs, |ip| {
+        match rmcp::ping(&ip.to_string(), timeout) {
+            Ok(rmcp::Pong {ipmi: true, ..}) => Some((ip, rmcp::auth_caps(&ip.to_string(), timeout))),
+            _ => None,
+        }
+    }).into_iter().flatten().collect();
+    if found.is_empty() {
+        println!("No BMCs found");
+        return;
+    }
+
+    // a BMC is known if it is added with the user to add it with, or any of the default ones to probe
+    let known = known(connection);
+    let users: Vec<String> = match (&opts.user, opts.probe_defaults, &profile) {
+        (Some(user), ..) => vec![user.clone()],
+        (None, true, _) => crate::default_credentials(connection).into_iter().map(|(user, _)| user).collect(),
+        (None, false, Some(Some(profile))) => vec![profile.user.clone()],
+        _ => Vec::new(),
+    };
+    let is_known = |ip: &Ipv4Addr| known.iter().any(|(k, _, user)| k == ip && users.contains(user));
+
+    println!();
+    println!("{:<15}  {:<12}  {:<24}  Hosts", "IP", "IPMI", "Auth");
+    println!("{:<15}  {:<12}  {:<24}  -----", "--", "----", "----");
+    for (ip, caps) in &found {
+        let hosts: Vec<String> = known.iter().filter(|(k, ..)| k == ip).map(|(_, id, user)| format!("{:>04} {}", id, user)).collect();
+        let (version, auth) = match caps {
+            Ok(caps) => (check::version(caps).to_string(), check::auth(caps)),
+            Err(e) => (String::from("?"), format!("failed: {}", e)),
+        };
+        println!("{:<15}  {:<12}  {:<24}  {}", ip, version, auth, hosts.join(", "));
+    }
+    println!();
+
+    let new: Vec<Ipv4Addr> = found.iter().map(|(ip, _)| *ip).filter(|ip| !is_known(ip)).collect();
+    println!("{} BMC(s) found, {} not added yet", found.len(), new.len());
+    if new.is_empty() {
+        return;
+    }
+    if users.is_empty() {
+        println!("Please add them with the user and password, a profile, or probe the default ones:");
+        println!("    ipmi.exe discover <network> -u <user> -p <pswd>");
+        println!("    ipmi.exe discover <network> --profile <profile>");
+        println!("    ipmi.exe discover <network> --probe-defaults");
+        return;
+    }
+    if !opts.yes && !crate::confirm(&format!("Add {} BMC(s) as hosts?", new.len())) {
+        return;
+    }
+
+    let hosts: Vec<(Host, Option<String>, Option<mc::DeviceId>)> = match (&opts.user, &opts.pswd) {
+        (Some(user), Some(pswd)) => new.iter().map(|ip| (Host {ip: ip.to_string(), user: user.clone(), pswd: pswd.clone()}, None, None)).collect(),
+        // the user and password are inherited from the profile
+        _ if opts.profile.is_some() => new.iter().map(|ip| (Host {ip: ip.to_string(), ..Default::default()}, None, None)).collect(),
+        _ => {
+            let (credentials, delay) = (crate::default_credentials(connection), opts.delay);
+            crate::parallel(new, opts.jobs, |ip| match Host::probe(&ip.to_string(), &credentials, delay) {
+                Some((host, device)) => Some((host, None, Some(device))),
+                None => {
+                    println!("{:<15} none of the {} default credentials logged in", ip, credentials.len());
+                    None
+                },
+            }).into_iter().flatten().collect()
+        },
+    };
+    let added = Host::add_all(connection, &hosts, opts.profile.as_deref());
+    for ((host, ..), (id, _)) in hosts.iter().zip(&added) {
+        if let Some(tag) = &opts.tag {
+            Host::tag(connection, *id, std::slice::from_ref(tag), false);
+        }
+        match &opts.profile {
+            Some(profile) => println!("{:>04} {:<15} added with profile {}", id, host.ip, profile),
+            None => println!("{:>04} {:<15} added with user {}", id, host.ip, host.user),
+        }
+    }
+}
+
+#[test]
+fn discover_addresses() {
+    let ips = addresses("10.245.38.0/24").unwrap();
+    assert_eq!(ips.len(), 254);
+    assert_eq!(ips[0], Ipv4Addr::new(10, 245, 38, 1));
+    assert_eq!(ips[253], Ipv4Addr::new(10, 245, 38, 254));
+    assert_eq!(addresses("10.245.38.17/30").unwrap(), vec![Ipv4Addr::new(10, 245, 38, 17), Ipv4Addr::new(10, 245, 38, 18)]);
+    assert_eq!(addresses("10.245.38.5/32").unwrap(), vec![Ipv4Addr::new(10, 245, 38, 5)]);
+    assert_eq!(addresses("10.245.38.250-10.245.39.2").unwrap().len(), 9);
+    assert_eq!(addresses("010.245.038.001").unwrap(), vec![Ipv4Addr::new(10, 245, 38, 1)]);
+    assert!(addresses("10.245.38.40-10.245.38.1").is_err());
+    assert!(addresses("10.0.0.0/8").is_err());
+    assert!(addresses("10.245.38.0/33").is_err());
+    assert!(addresses("fe80::1").is_err());
+
+    assert_eq!(offsets("1-40"), Ok((1, 40)));
+    assert!(offsets("40-1").is_err());
+}
@@ -0,0 +1,301 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::path;
+use structopt::StructOpt;
+
+use crate::fru_image::Image;
+use crate::{inventory, lan, mc, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub enum FruCommand {
+    /// Read the FRU inventory, BMC MAC and firmware of the host(s) into the database
+    Collect {
+        #[structopt(flatten)]
+        group: Group,
+    },
+    /// Edit the fields of a FRU image into a new image for `fru write`
+    Edit {
+        /// The field(s) to set, e.g. product.asset_tag=XYZ
+        #[structopt(short, long = "field", required = true, number_of_values = 1)]
+        fields: Vec<String>,
+        /// The image to edit instead of the one read from the current host
+        #[structopt(short, long)]
+        input: Option<path::PathBuf>,
+        /// The FRU device ID to read from the current host
+        #[structopt(short, long, default_value = "0")]
+        device: u8,
+        /// The new image
+        #[structopt(short, long)]
+        output: path::PathBuf,
+    },
+    /// Decode the fields of a FRU image
+    Decode {
+        file: path::PathBuf,
+    },
+}
+
+/// The `fru print` fields of the chassis, board and product areas and their `inventory` columns
+pub const FIELDS: &[(&str, &str)] = &[
+    ("Chassis Type",         "chassis_type"),
+    ("Chassis Part Number",  "chassis_part"),
+    ("Chassis Serial",       "chassis_serial"),
+    ("Board Mfg Date",       "board_date"),
+    ("Board Mfg",            "board_mfg"),
+    ("Board Product",        "board_product"),
+    ("Board Serial",         "board_serial"),
+    ("Board Part Number",    "board_part"),
+    ("Product Manufacturer", "product_mfg"),
+    ("Product Name",         "product_name"),
+    ("Product Part Number",  "product_part"),
+    ("Product Version",      "product_version"),
+    ("Product Serial",       "product_serial"),
+    ("Product Asset Tag",    "product_asset_tag"),
+];
+
+/// A FRU device, e.g. the builtin one (ID 0), a PSU or a DIMM
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Device {
+    pub id: i64,
+    pub name: String,
+    /// The area fields by `inventory` column, see `FIELDS`
+    pub fields: HashMap<&'static str, String>,
+}
+
+impl Device {
+    pub fn get(&self, column: &str) -> &str {
+        self.fields.get(column).map_or("", |v| v.as_str())
+    }
+    /// The identifying fields for lists, e.g. Samsung DDR4 DIMM M393A4K40CB2-CTD 36A1B2C3
+    pub fn summary(&self) -> String {
+        let board = ["board_mfg", "board_product", "board_part", "board_serial"];
+        let product = ["product_mfg", "product_name", "product_part", "product_serial"];
+        let columns = if self.get("product_name").is_empty() { board } else { product };
+        columns.iter().map(|c| self.get(c)).filter(|v| !v.is_empty()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Parse `ipmitool fru print` output, the devices not present and the empty fields are left out
+pub fn parse(output: &str) -> Vec<Device> {
+    let mut devices: Vec<Device> = Vec::new();
+    for line in output.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        if key == "FRU Device Description" {
+            // e.g. Builtin FRU Device (ID 0)
+            let (name, id) = match value.rsplit_once("(ID ") {
+                Some((name, id)) => (name.trim(), id.trim_end_matches(')').parse::<i64>().ok()),
+                None => (value, None),
+            };
+            devices.push(Device {id: id.unwrap_or(devices.len() as i64), name: String::from(name), ..Default::default()});
+        } else if let Some(device) = devices.last_mut() {
+            if let Some((_, column)) = FIELDS.iter().find(|(label, _)| *label == key && !value.is_empty()) {
+                device.fields.insert(column, String::from(value));
+            }
+        }
+    }
+    devices.retain(|d| !d.fields.is_empty());
+    devices
+}
+
+/// Replace the inventory of the host with the devices, and keep them as a snapshot if changed
+pub fn store(connection: &sqlite::Connection, host_id: i64, devices: &[Device], time: i64) {
+    connection.execute("BEGIN").unwrap();
+    let mut statement = connection.prepare("DELETE FROM inventory WHERE host_id=?").unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.next().unwrap();
+
+    let columns: Vec<&str> = FIELDS.iter().map(|(_, column)| *column).collect();
+    let mut statement = connection.prepare(format!(
+        "INSERT INTO inventory (host_id, device, name, {}, collected) VALUES (?, ?, ?, {}, ?)",
+        columns.join(", "), vec!["?"; columns.len()].join(", ")
+    )).unwrap();
+    for device in devices {
+        statement.reset().unwrap();
+        statement.bind(1, host_id).unwrap();
+        statement.bind(2, device.id).unwrap();
+        statement.bind(3, device.name.as_str()).unwrap();
+        for (i, column) in columns.iter().enumerate() {
+            statement.bind(4 + i, device.get(column)).unwrap();
+        }
+        statement.bind(4 + columns.len(), time).unwrap();
+        statement.next().unwrap();
+    }
+    inventory::snapshot(connection, host_id, devices, time);
+    connection.execute("COMMIT").unwrap();
+}
+
+/// Keep the BMC MAC and firmware of the host, the ones not read are left as they were
+pub fn store_details(connection: &sqlite::Connection, host_id: i64, mac: Option<&str>, firmware: Option<&str>) {
+    let mut statement = connection.prepare("INSERT OR IGNORE INTO details(host_id) VALUES (?)").unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.next().unwrap();
+    for (column, value) in [("mac", mac), ("firmware", firmware)] {
+        if let Some(value) = value {
+            let mut statement = connection.prepare(format!("UPDATE details SET {}=? WHERE host_id=?", column)).unwrap();
+            statement.bind(1, value).unwrap();
+            statement.bind(2, host_id).unwrap();
+            statement.next().unwrap();
+        }
+    }
+}
+
+/// Read the FRU image of the device with `fru read` through a file under the data directory
+fn read(host: &Login, device: u8) -> Result<Vec<u8>, String> {
+    let file = crate::datadir("fru").join(format!("{}-{}.bin", host.ip.replace(':', "-"), device));
+    host.run(&["fru", "read", &device.to_string(), &format!("\"{}\"", file.display())])?;
+    fs::read(&file).map_err(|e| e.to_string())
+}
+
+/// Set the `area.field=value` fields of the image, and tell the changes
+fn edit(image: &mut Image, fields: &[String]) -> Result<Vec<String>, String> {
+    let mut changes = Vec::new();
+    for field in fields {
+        let (path, value) = field.split_once('=').ok_or(format!("invalid field {}, expect e.g. product.asset_tag=XYZ", field))?;
+        let old = image.fields().into_iter().find(|(name, _)| name == path).map(|(_, text)| text);
+        image.set(path, value)?;
+        changes.push(format!("{}: {:?} -> {:?}", path, old.unwrap_or_default(), value));
+    }
+    Ok(changes)
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: FruCommand) {
+    match cmd {
+        FruCommand::Collect{group} => {
+            let hosts = Host::group_or_help(connection, group.tag.as_deref());
+            if hosts.is_empty() {
+                return;
+            }
+
+            let results = crate::parallel(hosts, group.jobs, |(id, host)| {
+                let devices = host.run(&["fru", "print"]).map(|output| parse(&output));
+                let mac = host.run(&["lan", "print"]).ok().and_then(|output| lan::value(&lan::parse(&output), "MAC Address").map(String::from));
+                let device = mc::device_id(&host).ok();
+                (id, host, devices, mac, device)
+            });
+            let time = crate::now();
+            for (id, host, devices, mac, device) in &results {
+                store_details(connection, *id, mac.as_deref(), None);
+                if let Some(device) = device {
+                    mc::store(connection, *id, device);
+                }
+                match devices {
+                    Ok(devices) => {
+                        store(connection, *id, devices, time);
+                        let builtin = devices.iter().find(|d| d.id == 0);
+                        println!("{:>04} {:<15} {} FRU device(s) {} {}", id, host.ip, devices.len(),
+                            builtin.map_or("", |d| d.get("product_name")), builtin.map_or("", |d| d.get("product_serial")));
+                    },
+                    Err(e) => println!("{:>04} {:<15} failed: {}", id, host.ip, e),
+                }
+            }
+        },
+        FruCommand::Edit{fields, input, device, output} => {
+            let dump = match &input {
+                Some(input) => fs::read(input).map_err(|e| e.to_string()),
+                None => match Host::group_or_help(connection, None).pop() {
+                    Some((_, host)) => read(&host, device),
+                    None => return,
+                },
+            };
+            let mut image = match dump.and_then(|dump| Image::parse(&dump)) {
+                Ok(image) => image,
+                Err(e) => {
+                    println!("Unable to read the FRU image: {}", e);
+                    return;
+                },
+            };
+            match edit(&mut image, &fields).and_then(|changes| image.to_bytes().map(|bytes| (changes, bytes))) {
+                Ok((changes, bytes)) => {
+                    if let Err(e) = fs::write(&output, &bytes) {
+                        println!("Unable to write {}: {}", output.display(), e);
+                        return;
+                    }
+                    for change in &changes {
+                        println!("{}", change);
+                    }
+                    if bytes.len() > image.size {
+                        println!("The new image is {} bytes larger than the FRU device, it may not be written", bytes.len() - image.size);
+                    }
+                    println!("Write the new image with command:");
+                    println!("    ipmi.exe -- fru write {} {}", device, output.display());
+                },
+                Err(e) => println!("Unable to edit the FRU image: {}", e),
+            }
+        },
+        FruCommand::Decode{file} => {
+            match fs::read(&file).map_err(|e| e.to_string()).and_then(|dump| Image::parse(&dump)) {
+                Ok(image) => {
+                    for (name, text) in image.fields() {
+                        println!("{:<24} {}", name, text);
+                    }
+                    for record in &image.multirecords {
+                        println!("{:<24} type 0x{:02x}, {} bytes", "multirecord", record.kind, record.data.len());
+                    }
+                },
+                Err(e) => println!("Unable to decode {}: {}", file.display(), e),
+            }
+        },
+    }
+}
+
+#[test]
+fn fru_parse() {
+    let devices = parse(&std::fs::read_to_string("tests/fixtures/fru_print_supermicro.txt").unwrap());
+    assert_eq!(devices.len(), 1);
+    assert_eq!((devices[0].id, devices[0].name.as_str()), (0, "Builtin FRU Device"));
+    assert_eq!(devices[0].get("chassis_serial"), "C8190LK15NT0123");
+    assert_eq!(devices[0].get("board_mfg"), "Supermicro");
+    assert_eq!(devices[0].get("board_part"), "X11DPU");
+    assert_eq!(devices[0].get("product_name"), "SYS-1029U-TRTP");
+    assert_eq!(devices[0].get("product_serial"), "S123456789");
+    assert_eq!(devices[0].get("product_asset_tag"), "");
+
+    let devices = parse(&std::fs::read_to_string("tests/fixtures/fru_print_dell.txt").unwrap());
+    let ids: Vec<(i64, &str)> = devices.iter().map(|d| (d.id, d.name.as_str())).collect();
+    assert_eq!(ids, vec![(0, "Builtin FRU Device"), (1, "PS1"), (17, "DIMM A1")]);
+    assert_eq!(devices[0].get("chassis_serial"), "");
+    assert_eq!(devices[0].get("product_asset_tag"), "RACK12-U07");
+    assert_eq!(devices[1].get("board_serial"), "CNLOD0089L0456");
+    assert_eq!(devices[2].get("board_part"), "M393A4K40CB2-CTD");
+}
+
+#[test]
+fn fru_store() {
+    let db_name = "fru.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database
+    };
+    std::fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '000.000.000.000', 'admin', 'admin')").unwrap();
+
+        let devices = parse(&std::fs::read_to_string("tests/fixtures/fru_print_dell.txt").unwrap());
+        store(&connection, 1, &devices, 1000);
+        store(&connection, 1, &devices[..1], 2000);
+
+        let mut statement = connection.prepare("SELECT device, product_name, product_serial, collected FROM inventory WHERE host_id=1").unwrap();
+        let mut rows = Vec::new();
+        while let sqlite::State::Row = statement.next().unwrap() {
+            rows.push((statement.read::<i64>(0).unwrap(), statement.read::<String>(1).unwrap(), statement.read::<String>(2).unwrap(), statement.read::<i64>(3).unwrap()));
+        }
+        assert_eq!(rows, vec![(0, String::from("PowerEdge R640"), String::from("7XKJ123"), 2000)]);
+    }
+    std::fs::remove_file(&database).unwrap();
+}
@@ -0,0 +1,334 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Parser and editor of the FRU binary image, see IPMI Platform Management FRU Information Storage Definition v1.0
+
+/// The end of the type/length fields of an info area
+const END_OF_FIELDS: u8 = 0xc1;
+
+pub const CHASSIS_FIELDS: &[&str] = &["part_number", "serial"];
+pub const BOARD_FIELDS: &[&str] = &["manufacturer", "product", "serial", "part_number", "fru_file_id"];
+pub const PRODUCT_FIELDS: &[&str] = &["manufacturer", "name", "part_number", "version", "serial", "asset_tag", "fru_file_id"];
+
+/// A type/length encoded field, kept as read so unchanged fields are written back the same
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// Bits 7:6 of the type/length byte: 0 binary, 1 BCD plus, 2 6-bit ASCII packed, 3 8-bit ASCII
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+impl Field {
+    pub fn ascii(text: &str) -> Result<Field, String> {
+        if !text.is_ascii() || text.len() > 0x3f {
+            return Err(format!("{} is not an ASCII text up to 63 characters", text));
+        }
+        let mut data = text.as_bytes().to_vec();
+        // a single character would be typed as the end of the fields, so it is padded with a NUL that `text` trims
+        if data.len() == 1 {
+            data.push(0x00);
+        }
+        Ok(Field {kind: 3, data})
+    }
+    pub fn text(&self) -> String {
+        match self.kind {
+            0 => self.data.iter().map(|b| format!("{:02x}", b)).collect(),
+            1 => self.data.iter().flat_map(|b| [b >> 4, b & 0x0f]).map(|n| b"0123456789 -.???"[n as usize] as char).collect(),
+            2 => (0..self.data.len() * 8 / 6).map(|i| {
+                let (byte, bit) = (6 * i / 8, 6 * i % 8);
+                let word = self.data[byte] as u16 | (*self.data.get(byte + 1).unwrap_or(&0) as u16) << 8;
+                char::from(((word >> bit) as u8 & 0x3f) + 0x20)
+            }).collect(),
+            _ => String::from_utf8_lossy(&self.data).trim_end_matches('\0').to_string(),
+        }
+    }
+}
+
+/// A chassis, board or product info area
+#[derive(Debug, Clone, PartialEq)]
+pub struct Area {
+    /// The bytes between the area length and the fields: chassis type, or language code (and mfg date for board)
+    pub head: Vec<u8>,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiRecord {
+    pub kind: u8,
+    pub version: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub internal: Option<Vec<u8>>,
+    pub chassis: Option<Area>,
+    pub board: Option<Area>,
+    pub product: Option<Area>,
+    pub multirecords: Vec<MultiRecord>,
+    /// The bytes after the last area, e.g. the unused space up to the size of the FRU device
+    pub tail: Vec<u8>,
+    pub size: usize,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg()
+}
+
+fn parse_area(image: &[u8], offset: usize, head: usize, name: &str) -> Result<(Area, usize), String> {
+    let area = image.get(offset..offset + 2).ok_or(format!("{} area out of the image", name))?;
+    let len = area[1] as usize * 8;
+    let area = image.get(offset..offset + len).filter(|_| len > 2 + head).ok_or(format!("{} area out of the image", name))?;
+    if area[0] != 0x01 {
+        return Err(format!("unsupported {} area format version {}", name, area[0]));
+    }
+    if checksum(area) != 0 {
+        return Err(format!("bad checksum of the {} area", name));
+    }
+
+    let mut fields = Vec::new();
+    let mut i = 2 + head;
+    loop {
+        let tl = *area.get(i).ok_or(format!("{} area fields without end", name))?;
+        if tl == END_OF_FIELDS {
+            break;
+        }
+        let data = area.get(i + 1..i + 1 + (tl & 0x3f) as usize).ok_or(format!("{} area field out of the area", name))?;
+        fields.push(Field {kind: tl >> 6, data: data.to_vec()});
+        i += 1 + data.len();
+    }
+    Ok((Area {head: area[2..2 + head].to_vec(), fields}, offset + len))
+}
+
+fn area_bytes(area: &Area) -> Vec<u8> {
+    let mut bytes = vec![0x01, 0x00];
+    bytes.extend(&area.head);
+    for field in &area.fields {
+        bytes.push(field.kind << 6 | field.data.len() as u8);
+        bytes.extend(&field.data);
+    }
+    bytes.push(END_OF_FIELDS);
+    while (bytes.len() + 1) % 8 != 0 {
+        bytes.push(0x00);
+    }
+    bytes[1] = ((bytes.len() + 1) / 8) as u8;
+    bytes.push(checksum(&bytes));
+    bytes
+}
+
+impl Image {
+    pub fn parse(image: &[u8]) -> Result<Image, String> {
+        let header = image.get(..8).ok_or("image shorter than the common header")?;
+        if header[0] & 0x0f != 0x01 {
+            return Err(format!("unsupported common header format version {}", header[0]));
+        }
+        if checksum(header) != 0 {
+            return Err(String::from("bad checksum of the common header"));
+        }
+        let offsets: Vec<usize> = header[1..6].iter().map(|o| *o as usize * 8).collect();
+        let mut end = 8;
+
+        let internal = match offsets[0] {
+            0 => None,
+            offset => {
+                // the internal use area has no length, it runs up to the next area
+                let next = offsets[1..].iter().filter(|o| **o > offset).min().copied().unwrap_or(image.len());
+                end = end.max(next);
+                Some(image.get(offset..next).ok_or("internal use area out of the image")?.to_vec())
+            },
+        };
+        let mut area = |i: usize, head: usize, name: &str| -> Result<Option<Area>, String> {
+            match offsets[i] {
+                0 => Ok(None),
+                offset => {
+                    let (area, next) = parse_area(image, offset, head, name)?;
+                    end = end.max(next);
+                    Ok(Some(area))
+                },
+            }
+        };
+        let chassis = area(1, 1, "chassis")?;
+        let board = area(2, 4, "board")?;
+        let product = area(3, 1, "product")?;
+
+        let mut multirecords = Vec::new();
+        if offsets[4] != 0 {
+            let mut offset = offsets[4];
+            loop {
+                let header = image.get(offset..offset + 5).ok_or("multirecord out of the image")?;
+                if checksum(header) != 0 {
+                    return Err(format!("bad header checksum of the multirecord at {}", offset));
+                }
+                let data = image.get(offset + 5..offset + 5 + header[2] as usize).ok_or("multirecord out of the image")?;
+                if data.iter().fold(header[3], |sum, b| sum.wrapping_add(*b)) != 0 {
+                    return Err(format!("bad checksum of the multirecord at {}", offset));
+                }
+                multirecords.push(MultiRecord {kind: header[0], version: header[1] & 0x0f, data: data.to_vec()});
+                offset += 5 + data.len();
+                if header[1] & 0x80 != 0 {
+                    break;
+                }
+            }
+            end = end.max(offset);
+        }
+
+        Ok(Image {internal, chassis, board, product, multirecords, tail: image[end..].to_vec(), size: image.len()})
+    }
+    /// The image with the areas laid out in order, filled up to the original size with the tail as far as the areas fit
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        // the common header keeps the offsets in multiples of 8 bytes in a byte each
+        let offset = |bytes: &Vec<u8>| Some((8 + bytes.len()) / 8).filter(|o| *o <= 0xff).map(|o| o as u8)
+            .ok_or(format!("the areas start beyond the {} bytes the common header can point to", 0xff * 8));
+        let mut header = vec![0x01, 0, 0, 0, 0, 0, 0x00];
+        let mut bytes = Vec::new();
+        if let Some(internal) = &self.internal {
+            header[1] = offset(&bytes)?;
+            bytes.extend(internal);
+            bytes.resize(bytes.len().div_ceil(8) * 8, 0x00);
+        }
+        for (i, area) in [(2, &self.chassis), (3, &self.board), (4, &self.product)] {
+            if let Some(area) = area {
+                header[i] = offset(&bytes)?;
+                bytes.extend(area_bytes(area));
+            }
+        }
+        for (i, record) in self.multirecords.iter().enumerate() {
+            if i == 0 {
+                header[5] = offset(&bytes)?;
+            }
+            let end = if i + 1 == self.multirecords.len() { 0x80 } else { 0x00 };
+            let mut head = vec![record.kind, end | record.version, record.data.len() as u8, checksum(&record.data)];
+            head.push(checksum(&head));
+            bytes.extend(head);
+            bytes.extend(&record.data);
+        }
+        header.push(checksum(&header));
+
+        header.extend(bytes);
+        let fill = self.size.saturating_sub(header.len());
+        header.resize(header.len() + fill.saturating_sub(self.tail.len()), 0x00);
+        header.extend(&self.tail[self.tail.len() - fill.min(self.tail.len())..]);
+        Ok(header)
+    }
+    fn area(&self, name: &str) -> Option<(&Area, &'static [&'static str])> {
+        match name {
+            "chassis" => self.chassis.as_ref().map(|a| (a, CHASSIS_FIELDS)),
+            "board"   => self.board.as_ref().map(|a| (a, BOARD_FIELDS)),
+            "product" => self.product.as_ref().map(|a| (a, PRODUCT_FIELDS)),
+            _         => None,
+        }
+    }
+    /// The fields as `area.field` and their text, the ones after the named fields as `area.custom<n>`
+    pub fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        for name in ["chassis", "board", "product"] {
+            if let Some((area, names)) = self.area(name) {
+                for (i, field) in area.fields.iter().enumerate() {
+                    let field_name = names.get(i).map_or_else(|| format!("custom{}", i - names.len() + 1), |n| n.to_string());
+                    fields.push((format!("{}.{}", name, field_name), field.text()));
+                }
+            }
+        }
+        fields
+    }
+    /// Set the field as 8-bit ASCII, e.g. `product.asset_tag`, a custom field can be appended as the next `custom<n>`
+    pub fn set(&mut self, path: &str, text: &str) -> Result<(), String> {
+        let (name, field_name) = path.split_once('.').ok_or(format!("invalid field {}, expect e.g. product.asset_tag", path))?;
+        let (area, names) = match name {
+            "chassis" => (&mut self.chassis, CHASSIS_FIELDS),
+            "board"   => (&mut self.board, BOARD_FIELDS),
+            "product" => (&mut self.product, PRODUCT_FIELDS),
+            _         => return Err(format!("invalid area {}, expect one of chassis, board or product", name)),
+        };
+        let area = area.as_mut().ok_or(format!("no {} area in the image", name))?;
+        let i = match names.iter().position(|n| *n == field_name) {
+            Some(i) => i,
+            None => match field_name.strip_prefix("custom").and_then(|n| n.parse::<usize>().ok()).filter(|n| *n > 0) {
+                Some(n) => names.len() + n - 1,
+                None => return Err(format!("invalid field {}, expect one of {} or custom<n>", path, names.join(", "))),
+            },
+        };
+        if i > area.fields.len() || (i == area.fields.len() && i < names.len()) {
+            return Err(format!("no field {} in the {} area", field_name, name));
+        }
+        let field = Field::ascii(text)?;
+        if i == area.fields.len() {
+            area.fields.push(field);
+        } else {
+            area.fields[i] = field;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn fru_image_parse() {
+    let image = Image::parse(&std::fs::read("tests/fixtures/fru_supermicro.bin").unwrap()).unwrap();
+    assert_eq!(image.internal.as_ref().map(|i| i.len()), Some(8));
+    assert_eq!(image.chassis.as_ref().map(|c| c.head.clone()), Some(vec![0x17]));
+    assert_eq!(image.multirecords.iter().map(|r| (r.kind, r.data.len())).collect::<Vec<_>>(), vec![(0x00, 24), (0x01, 13)]);
+    let fields = image.fields();
+    let field = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, t)| t.as_str());
+    assert_eq!(field("chassis.serial"), Some("C8190LK15NT0123"));
+    assert_eq!(field("board.product"), Some("X11DPU"));
+    assert_eq!(field("board.fru_file_id"), Some(""));
+    assert_eq!(field("board.custom1"), Some("CUSTOM"));
+    assert_eq!(field("product.serial"), Some("S123456789"));
+    assert_eq!(field("product.asset_tag"), Some(""));
+
+    let image = Image::parse(&std::fs::read("tests/fixtures/fru_dell.bin").unwrap()).unwrap();
+    assert!(image.chassis.is_none() && image.internal.is_none() && image.multirecords.is_empty());
+    let fields = image.fields();
+    let field = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, t)| t.as_str());
+    assert_eq!(field("board.manufacturer"), Some("DELL"));
+    assert_eq!(field("board.serial"), Some("20190312"));
+    assert_eq!(field("product.asset_tag"), Some("RACK12-U07"));
+
+    let mut bad = std::fs::read("tests/fixtures/fru_dell.bin").unwrap();
+    bad[20] ^= 0xff;
+    assert_eq!(Image::parse(&bad), Err(String::from("bad checksum of the board area")));
+    assert!(Image::parse(&[0x01, 0x00]).is_err());
+}
+
+#[test]
+fn fru_image_round_trip() {
+    for fixture in ["tests/fixtures/fru_supermicro.bin", "tests/fixtures/fru_dell.bin"] {
+        let dump = std::fs::read(fixture).unwrap();
+        let image = Image::parse(&dump).unwrap();
+        assert_eq!(image.to_bytes().unwrap(), dump);
+
+        // the edited image keeps its size and parses back with the new field
+        let mut edited = image.clone();
+        edited.set("product.asset_tag", "ASSET-0001-LONGER-THAN-BEFORE").unwrap();
+        let bytes = edited.to_bytes().unwrap();
+        assert_eq!(bytes.len(), dump.len());
+        assert_eq!(Image::parse(&bytes).unwrap().fields(), edited.fields());
+    }
+
+    let mut image = Image::parse(&std::fs::read("tests/fixtures/fru_supermicro.bin").unwrap()).unwrap();
+    assert!(image.set("product.custom1", "EXTRA").is_ok());
+    assert!(image.set("product.custom3", "EXTRA").is_err());
+    assert!(image.set("product.colour", "RED").is_err());
+    assert!(image.set("system.serial", "X").is_err());
+    assert!(image.set("product.serial", &"X".repeat(64)).is_err());
+
+    // case: a single character, which is not to end the fields after it
+    image.set("product.version", "A").unwrap();
+    let fields = Image::parse(&image.to_bytes().unwrap()).unwrap().fields();
+    assert_eq!(fields, image.fields());
+    assert!(fields.contains(&(String::from("product.version"), String::from("A"))));
+    assert!(fields.contains(&(String::from("product.custom1"), String::from("EXTRA"))));
+
+    // case: an area beyond the offsets the common header can keep
+    let mut image = Image::parse(&std::fs::read("tests/fixtures/fru_dell.bin").unwrap()).unwrap();
+    image.internal = Some(vec![0x00; 0xff * 8]);
+    assert!(image.to_bytes().is_err());
+}
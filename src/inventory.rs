@@ -0,0 +1,266 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+use structopt::StructOpt;
+
+use crate::fru::{Device, FIELDS};
+use crate::{Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub enum InventoryCommand {
+    /// Show the FRU devices added, removed and changed between two inventory snapshots of a host
+    Diff {
+        /// The index of the host
+        id: i64,
+        /// The snapshot at the time, e.g. 2020-06-01, "2020-06-01 08:00" or 7d (ago), the one before the last by default
+        #[structopt(short, long, parse(try_from_str = time))]
+        from: Option<i64>,
+        /// The snapshot at the time, the last one by default
+        #[structopt(long, parse(try_from_str = time))]
+        to: Option<i64>,
+    },
+    /// List the FRU device changes of the hosts
+    Changes {
+        /// The changes of the last <since>, e.g. 24h or 7d
+        #[structopt(short, long, default_value = "7d", parse(try_from_str = crate::seconds))]
+        since: u64,
+        /// Only the hosts with the tag
+        #[structopt(short, long)]
+        tag: Option<String>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Added(Device),
+    Removed(Device),
+    /// The device ID, name and the changed fields with the old and new value
+    Changed(i64, String, Vec<(&'static str, String, String)>),
+}
+
+impl Change {
+    fn row(&self) -> (char, i64, &str, String) {
+        match self {
+            Change::Added(d)   => ('+', d.id, d.name.as_str(), d.summary()),
+            Change::Removed(d) => ('-', d.id, d.name.as_str(), d.summary()),
+            Change::Changed(id, name, fields) => {
+                let fields: Vec<String> = fields.iter().map(|(c, old, new)| format!("{}: {} -> {}", c, old, new)).collect();
+                ('~', *id, name.as_str(), fields.join(", "))
+            },
+        }
+    }
+}
+
+/// A time of now minus a duration, or a local date and time
+fn time(s: &str) -> Result<i64, String> {
+    use chrono::{NaiveDate, NaiveDateTime, TimeZone};
+
+    if let Ok(seconds) = crate::seconds(s) {
+        return Ok(crate::now() - seconds as i64);
+    }
+    let t = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"].iter().find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(23, 59, 59)))
+        .ok_or(format!("invalid time {}, expect e.g. 2020-06-01, \"2020-06-01 08:00\" or 7d", s))?;
+    chrono::Local.from_local_datetime(&t).earliest().map(|t| t.timestamp()).ok_or(format!("invalid local time {}", s))
+}
+
+/// The times of the snapshots of the host, in order
+fn times(connection: &sqlite::Connection, host_id: i64) -> Vec<i64> {
+    let mut statement = connection.prepare("SELECT DISTINCT time FROM inventory_snapshots WHERE host_id=? ORDER BY time").unwrap();
+    statement.bind(1, host_id).unwrap();
+    let mut times = Vec::new();
+    while let sqlite::State::Row = statement.next().unwrap() {
+        times.push(statement.read::<i64>(0).unwrap());
+    }
+    times
+}
+
+fn load(connection: &sqlite::Connection, host_id: i64, time: i64) -> Vec<Device> {
+    let mut statement = connection.prepare(format!(
+        "SELECT device, name, {} FROM inventory_snapshots WHERE host_id=? AND time=? ORDER BY device",
+        FIELDS.iter().map(|(_, column)| *column).collect::<Vec<_>>().join(", ")
+    )).unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.bind(2, time).unwrap();
+    let mut devices = Vec::new();
+    while let sqlite::State::Row = statement.next().unwrap() {
+        let mut device = Device {id: statement.read::<i64>(0).unwrap(), name: statement.read::<String>(1).unwrap(), ..Default::default()};
+        for (i, (_, column)) in FIELDS.iter().enumerate() {
+            let value = statement.read::<String>(2 + i).unwrap();
+            if !value.is_empty() {
+                device.fields.insert(column, value);
+            }
+        }
+        devices.push(device);
+    }
+    devices
+}
+
+/// Keep the devices as a snapshot of the host at the time, unless they are the same as the last one
+pub fn snapshot(connection: &sqlite::Connection, host_id: i64, devices: &[Device], time: i64) {
+    if devices.is_empty() {
+        return;
+    }
+    if let Some(last) = times(connection, host_id).pop() {
+        if diff(&load(connection, host_id, last), devices).is_empty() {
+            return;
+        }
+    }
+
+    let columns: Vec<&str> = FIELDS.iter().map(|(_, column)| *column).collect();
+    let mut statement = connection.prepare(format!(
+        "INSERT INTO inventory_snapshots (host_id, time, device, name, {}) VALUES (?, ?, ?, ?, {})",
+        columns.join(", "), vec!["?"; columns.len()].join(", ")
+    )).unwrap();
+    for device in devices {
+        statement.reset().unwrap();
+        statement.bind(1, host_id).unwrap();
+        statement.bind(2, time).unwrap();
+        statement.bind(3, device.id).unwrap();
+        statement.bind(4, device.name.as_str()).unwrap();
+        for (i, column) in columns.iter().enumerate() {
+            statement.bind(5 + i, device.get(column)).unwrap();
+        }
+        statement.next().unwrap();
+    }
+}
+
+/// The changes from one list of devices to another, matched by the FRU device ID
+pub fn diff(from: &[Device], to: &[Device]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for old in from {
+        match to.iter().find(|d| d.id == old.id) {
+            None => changes.push(Change::Removed(old.clone())),
+            Some(new) => {
+                let mut fields: Vec<(&'static str, String, String)> = FIELDS.iter()
+                    .filter(|(_, c)| old.get(c) != new.get(c))
+                    .map(|(_, c)| (*c, String::from(old.get(c)), String::from(new.get(c))))
+                    .collect();
+                if old.name != new.name {
+                    fields.insert(0, ("name", old.name.clone(), new.name.clone()));
+                }
+                if !fields.is_empty() {
+                    changes.push(Change::Changed(new.id, new.name.clone(), fields));
+                }
+            },
+        }
+    }
+    for new in to.iter().filter(|d| !from.iter().any(|old| old.id == d.id)) {
+        changes.push(Change::Added(new.clone()));
+    }
+    changes.sort_by_key(|c| match c { Change::Added(d) | Change::Removed(d) => d.id, Change::Changed(id, ..) => *id });
+    changes
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: InventoryCommand) {
+    match cmd {
+        InventoryCommand::Diff{id, from, to} => {
+            let mut statement = connection.prepare("SELECT ip FROM hosts WHERE id=?").unwrap();
+            statement.bind(1, id).unwrap();
+            let ip = match statement.next().unwrap() {
+                sqlite::State::Row => statement.read::<String>(0).unwrap(),
+                sqlite::State::Done => {
+                    println!("Please list and find available <index of host>:");
+                    println!("    ipmi.exe host list");
+                    return;
+                },
+            };
+
+            let times = times(connection, id);
+            let at = |t: Option<i64>, default: Option<i64>| match t {
+                Some(t) => times.iter().rev().find(|s| **s <= t).copied(),
+                None => default,
+            };
+            let to = at(to, times.last().copied());
+            let from = at(from, to.and_then(|to| times.iter().rev().find(|s| **s < to).copied()));
+            let (from, to) = match (from, to) {
+                (Some(from), Some(to)) => (from, to),
+                _ => {
+                    println!("No inventory snapshots of {} to compare, collect them with command:", ip);
+                    println!("    ipmi.exe fru collect");
+                    return;
+                },
+            };
+
+            println!("{} {} -> {}", ip, crate::datetime(from), crate::datetime(to));
+            let changes = diff(&load(connection, id, from), &load(connection, id, to));
+            if changes.is_empty() {
+                println!("No changes");
+            }
+            for change in &changes {
+                let (mark, device, name, detail) = change.row();
+                println!("{} {:>4} {:<24} {}", mark, device, name, detail);
+            }
+        },
+        InventoryCommand::Changes{since, tag} => {
+            let from = crate::now() - since as i64;
+            let hosts: Vec<(i64, Login)> = match tag {
+                Some(tag) => Host::group(connection, Some(&tag)),
+                None => Host::all(connection),
+            };
+
+            println!("{:<15}  {:<19}  {:<4} {:<24} Detail", "Host", "Time", "Id", "Device");
+            for (id, host) in &hosts {
+                let times = times(connection, *id);
+                for pair in times.windows(2).filter(|pair| pair[1] >= from) {
+                    for change in diff(&load(connection, *id, pair[0]), &load(connection, *id, pair[1])) {
+                        let (mark, device, name, detail) = change.row();
+                        println!("{:<15}  {:<19}  {:>4} {:<24} {} {}", host.ip, crate::datetime(pair[1]), device, name, mark, detail);
+                    }
+                }
+            }
+        },
+    }
+}
+
+#[test]
+fn inventory_diff() {
+    let before = crate::fru::parse(&std::fs::read_to_string("tests/fixtures/fru_print_dell.txt").unwrap());
+    let mut after = crate::fru::parse(&std::fs::read_to_string("tests/fixtures/fru_print_dell.txt").unwrap());
+    assert!(diff(&before, &after).is_empty());
+
+    after[1].fields.insert("board_serial", String::from("CNLOD0089L0999"));
+    let dimm = after.remove(2);
+    after.push(Device {id: 18, name: String::from("DIMM A2"), fields: dimm.fields.clone()});
+    let changes = diff(&before, &after);
+    assert_eq!(changes.len(), 3);
+    assert_eq!(changes[0], Change::Changed(1, String::from("PS1"), vec![("board_serial", String::from("CNLOD0089L0456"), String::from("CNLOD0089L0999"))]));
+    assert!(matches!(&changes[1], Change::Removed(d) if d.id == 17));
+    assert!(matches!(&changes[2], Change::Added(d) if d.id == 18 && d.summary() == "Samsung DDR4 DIMM M393A4K40CB2-CTD 36A1B2C3"));
+}
+
+#[test]
+fn inventory_snapshot() {
+    let db_name = "inventory.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database
+    };
+    std::fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '000.000.000.000', 'admin', 'admin')").unwrap();
+
+        let devices = crate::fru::parse(&std::fs::read_to_string("tests/fixtures/fru_print_dell.txt").unwrap());
+        crate::fru::store(&connection, 1, &devices, 1000);
+        crate::fru::store(&connection, 1, &devices, 2000);
+        assert_eq!(times(&connection, 1), vec![1000]);
+
+        crate::fru::store(&connection, 1, &devices[..2], 3000);
+        assert_eq!(times(&connection, 1), vec![1000, 3000]);
+        assert_eq!(load(&connection, 1, 1000), devices);
+        let changes = diff(&load(&connection, 1, 1000), &load(&connection, 1, 3000));
+        assert!(matches!(&changes[..], [Change::Removed(d)] if d.name == "DIMM A1"));
+    }
+    std::fs::remove_file(&database).unwrap();
+}
@@ -0,0 +1,601 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::path;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use crate::{mc, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub enum LanCommand {
+    /// Read the LAN configuration of the host(s) into the database
+    Capture {
+        /// The LAN channel
+        #[structopt(short, long, default_value = "1")]
+        channel: u8,
+        #[structopt(flatten)]
+        group: Group,
+    },
+    /// Compare the captured LAN configuration of the host(s) with the desired one
+    Diff {
+        /// The desired LAN configuration (YAML, or TOML by the .toml extension)
+        spec: path::PathBuf,
+        /// The LAN channel
+        #[structopt(short, long, default_value = "1")]
+        channel: u8,
+        #[structopt(flatten)]
+        group: Group,
+    },
+    /// Set the LAN configuration of the host(s) that differs from the desired one
+    Apply {
+        /// The desired LAN configuration (YAML, or TOML by the .toml extension)
+        spec: path::PathBuf,
+        /// The LAN channel
+        #[structopt(short, long, default_value = "1")]
+        channel: u8,
+        /// Print the `lan set` commands without running them
+        #[structopt(short = "n", long)]
+        dry_run: bool,
+        /// Apply without asking for confirmation
+        #[structopt(short, long)]
+        yes: bool,
+        /// Seconds to wait for the BMC to answer at its new address before reverting
+        #[structopt(long, default_value = "120")]
+        timeout: u64,
+        #[structopt(flatten)]
+        group: Group,
+    },
+}
+
+/// The LAN configuration parameters of a channel that can be set
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lan {
+    /// static, dhcp, bios or other
+    pub ip_source: String,
+    pub ip: String,
+    pub netmask: String,
+    pub gateway: String,
+    /// 0 if the VLAN is disabled
+    pub vlan: u16,
+    pub vlan_priority: u8,
+    /// The max privilege of each cipher suite, e.g. XaaaXXaaaXXaaXX
+    pub cipher_privs: String,
+    pub mac: String,
+}
+
+/// The desired LAN configuration, the settings left out are not changed, e.g.
+///
+/// ```yaml
+/// netmask: 255.255.255.0
+/// vlan: 100
+/// cipher_privs: XXXaXXXXXXXXXXX
+/// hosts:
+///   10.245.38.1:
+///     gateway: 10.245.38.254
+/// ```
+#[derive(Deserialize, Debug, Default)]
+pub struct Spec {
+    #[serde(flatten)]
+    pub settings: Settings,
+    /// The settings of single hosts by their IP, over the common ones
+    #[serde(default)]
+    pub hosts: HashMap<String, Settings>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Settings {
+    pub ip_source: Option<String>,
+    pub ip: Option<String>,
+    pub netmask: Option<String>,
+    pub gateway: Option<String>,
+    /// 0 to disable the VLAN
+    pub vlan: Option<u16>,
+    pub vlan_priority: Option<u8>,
+    pub cipher_privs: Option<String>,
+}
+
+/// A setting to change, with its `lan set` arguments after the channel, and the ones to set it back if possible
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub setting: &'static str,
+    pub from: String,
+    pub to: String,
+    pub args: Vec<String>,
+    pub undo: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Applied {
+    /// Answering at the new IP, if it was changed
+    Done(Option<String>),
+    /// Not answering after the changes, and answering again after they were set back
+    Reverted(String),
+    /// Not answering, neither after the changes were set back
+    Stranded(String),
+}
+
+/// Parse `ipmitool lan print` output into the parameters and their values, the continuation lines
+/// (e.g. of Auth Type Enable) are left out
+pub fn parse(output: &str) -> Vec<(String, String)> {
+    output.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(key, _)| !key.trim().is_empty())
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+pub fn value<'a>(params: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+}
+
+impl Lan {
+    pub fn from_params(params: &[(String, String)]) -> Lan {
+        let value = |key| String::from(value(params, key).unwrap_or(""));
+        let ip_source = match value("IP Address Source").as_str() {
+            "Static Address"        => "static",
+            "DHCP Address"          => "dhcp",
+            "BIOS Assigned Address" => "bios",
+            _                       => "other",
+        };
+        Lan {
+            ip_source: String::from(ip_source),
+            ip: value("IP Address"),
+            netmask: value("Subnet Mask"),
+            gateway: value("Default Gateway IP"),
+            vlan: value("802.1q VLAN ID").parse().unwrap_or(0),
+            vlan_priority: value("802.1q VLAN Priority").parse().unwrap_or(0),
+            cipher_privs: value("Cipher Suite Priv Max"),
+            mac: value("MAC Address"),
+        }
+    }
+}
+
+impl Settings {
+    /// Check the values before any of them is set
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(source) = &self.ip_source {
+            if source != "static" && source != "dhcp" {
+                return Err(format!("invalid ip_source {}, expect static or dhcp", source));
+            }
+        }
+        for (name, ip) in [("ip", &self.ip), ("netmask", &self.netmask), ("gateway", &self.gateway)] {
+            if let Some(ip) = ip {
+                if ip.parse::<std::net::Ipv4Addr>().is_err() {
+                    return Err(format!("invalid {} {}", name, ip));
+                }
+            }
+        }
+        if self.vlan.is_some_and(|vlan| vlan > 4094) {
+            return Err(String::from("invalid vlan, expect 1 to 4094 or 0 to disable it"));
+        }
+        if self.vlan_priority.is_some_and(|priority| priority > 7) {
+            return Err(String::from("invalid vlan_priority, expect 0 to 7"));
+        }
+        if let Some(privs) = &self.cipher_privs {
+            if privs.len() != 15 || !privs.chars().all(|c| "XcuoaO".contains(c)) {
+                return Err(format!("invalid cipher_privs {}, expect 15 of X, c, u, o, a or O", privs));
+            }
+        }
+        Ok(())
+    }
+    fn or(&self, common: &Settings) -> Settings {
+        Settings {
+            ip_source: self.ip_source.clone().or_else(|| common.ip_source.clone()),
+            ip: self.ip.clone().or_else(|| common.ip.clone()),
+            netmask: self.netmask.clone().or_else(|| common.netmask.clone()),
+            gateway: self.gateway.clone().or_else(|| common.gateway.clone()),
+            vlan: self.vlan.or(common.vlan),
+            vlan_priority: self.vlan_priority.or(common.vlan_priority),
+            cipher_privs: self.cipher_privs.clone().or_else(|| common.cipher_privs.clone()),
+        }
+    }
+}
+
+impl Spec {
+    pub fn load(file: &path::Path) -> Result<Spec, String> {
+        let text = fs::read_to_string(file).map_err(|e| e.to_string())?;
+        let spec: Spec = if file.extension().is_some_and(|x| x == "toml") {
+            toml::from_str(&text).map_err(|e| e.to_string())?
+        } else {
+            serde_yaml::from_str(&text).map_err(|e| e.to_string())?
+        };
+        spec.settings.validate()?;
+        for (ip, settings) in &spec.hosts {
+            settings.validate().map_err(|e| format!("{}: {}", ip, e))?;
+        }
+        if spec.settings.ip.is_some() {
+            return Err(String::from("ip can only be set for single hosts"));
+        }
+        Ok(spec)
+    }
+    /// The desired settings of the host
+    pub fn settings(&self, ip: &str) -> Settings {
+        self.hosts.get(ip).map_or_else(|| self.settings.clone(), |s| s.or(&self.settings))
+    }
+}
+
+/// The changes from the current configuration to the desired one, in the order to set them:
+/// the ones which may cut the connection (VLAN and IP) last
+pub fn changes(current: &Lan, desired: &Settings) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut change = |setting, from: String, to: String, args: &[&str], undo: Option<&[&str]>| {
+        if from != to {
+            let owned = |args: &[&str]| args.iter().map(|a| a.to_string()).collect();
+            changes.push(Change {setting, from, to, args: owned(args), undo: undo.map(owned)});
+        }
+    };
+    let vlan_id = |vlan: u16| if vlan == 0 { String::from("off") } else { vlan.to_string() };
+    if let Some(privs) = &desired.cipher_privs {
+        let undo = Some(["cipher_privs", current.cipher_privs.as_str()]).filter(|_| !current.cipher_privs.is_empty());
+        change("cipher_privs", current.cipher_privs.clone(), privs.clone(), &["cipher_privs", privs], undo.as_ref().map(|u| &u[..]));
+    }
+    if let Some(source) = &desired.ip_source {
+        let undo = Some(["ipsrc", current.ip_source.as_str()]).filter(|_| current.ip_source != "other");
+        change("ip_source", current.ip_source.clone(), source.clone(), &["ipsrc", source], undo.as_ref().map(|u| &u[..]));
+    }
+    if let Some(netmask) = &desired.netmask {
+        change("netmask", current.netmask.clone(), netmask.clone(), &["netmask", netmask], Some(&["netmask", &current.netmask]));
+    }
+    if let Some(gateway) = &desired.gateway {
+        change("gateway", current.gateway.clone(), gateway.clone(), &["defgw", "ipaddr", gateway], Some(&["defgw", "ipaddr", &current.gateway]));
+    }
+    if let Some(priority) = desired.vlan_priority {
+        let (from, to) = (current.vlan_priority.to_string(), priority.to_string());
+        change("vlan_priority", from.clone(), to.clone(), &["vlan", "priority", &to], Some(&["vlan", "priority", &from]));
+    }
+    if let Some(vlan) = desired.vlan {
+        let (from, to) = (vlan_id(current.vlan), vlan_id(vlan));
+        change("vlan", current.vlan.to_string(), vlan.to_string(), &["vlan", "id", &to], Some(&["vlan", "id", &from]));
+    }
+    if let Some(ip) = &desired.ip {
+        change("ip", current.ip.clone(), ip.clone(), &["ipaddr", ip], Some(&["ipaddr", &current.ip]));
+    }
+    changes
+}
+
+/// Read the LAN configuration of the channel
+pub fn read(host: &Login, channel: u8) -> Result<Lan, String> {
+    let output = host.run(&["lan", "print", &channel.to_string()])?;
+    let params = parse(&output);
+    if value(&params, "IP Address").is_none() {
+        return Err(format!("unexpected lan print output: {}", output.trim()));
+    }
+    Ok(Lan::from_params(&params))
+}
+
+fn set(host: &Login, channel: u8, args: &[String]) -> Result<String, String> {
+    let mut set = vec!["lan", "set"];
+    let channel = channel.to_string();
+    set.push(&channel);
+    set.extend(args.iter().map(|a| a.as_str()));
+    host.run(&set)
+}
+
+/// Wait for the BMC to answer Get Device ID
+fn answers(host: &Login, timeout: u64) -> bool {
+    let start = Instant::now();
+    loop {
+        if mc::device_id(host).is_ok() {
+            return true;
+        }
+        if start.elapsed() >= Duration::from_secs(timeout) {
+            return false;
+        }
+        thread::sleep(Duration::from_secs(5).min(Duration::from_secs(timeout).saturating_sub(start.elapsed())));
+    }
+}
+
+/// Set the changes, then check that the BMC answers at its (new) address within the timeout, or set the changes
+/// back through the old address, and the new one if the old does not work
+fn apply(host: &Login, channel: u8, changes: &[Change], timeout: u64, log: &dyn Fn(&str)) -> Applied {
+    let mut applied = 0;
+    let mut failure = None;
+    for c in changes {
+        // a failed change may have been set anyway, e.g. if the connection was cut by it
+        applied += 1;
+        match set(host, channel, &c.args) {
+            Ok(_) => log(&format!("{} set to {}", c.setting, c.to)),
+            Err(e) => {
+                failure = Some(format!("{} failed: {}", c.setting, e));
+                break;
+            },
+        }
+    }
+    let ip = changes[..applied].iter().find(|c| c.setting == "ip").map(|c| c.to.clone());
+    let target = Login {host: Host {ip: ip.clone().unwrap_or_else(|| host.ip.clone()), ..host.host.clone()}, ..host.clone()};
+    let reason = match failure {
+        None if answers(&target, timeout) => return Applied::Done(ip),
+        None => format!("no answer at {} within {}s", target.ip, timeout),
+        Some(failure) => failure,
+    };
+
+    log(&format!("{}, setting the changes back", reason));
+    let paths = if ip.is_some() { vec![host, &target] } else { vec![host] };
+    for path in paths {
+        let undone = changes[..applied].iter().rev().all(|c| match &c.undo {
+            Some(undo) => set(path, channel, undo).map_err(|e| log(&format!("{} not set back through {}: {}", c.setting, path.ip, e))).is_ok(),
+            None => {
+                log(&format!("{} can not be set back to {}", c.setting, c.from));
+                true
+            },
+        });
+        if undone && answers(host, timeout) {
+            return Applied::Reverted(reason);
+        }
+    }
+    Applied::Stranded(reason)
+}
+
+/// Move the host to its new IP, which fails if the host is there with the same user already
+fn update_ip(connection: &sqlite::Connection, host_id: i64, ip: &str) -> Result<(), String> {
+    let mut statement = connection.prepare("UPDATE hosts SET ip=? WHERE id=?").unwrap();
+    statement.bind(1, ip).unwrap();
+    statement.bind(2, host_id).unwrap();
+    statement.next().map(|_| ()).map_err(|e| e.to_string())
+}
+
+pub fn capture(connection: &sqlite::Connection, host_id: i64, channel: u8, lan: &Lan, time: i64) {
+    let mut statement = connection.prepare(
+        "INSERT INTO lan_captures (host_id, channel, time, ip_source, ip, netmask, gateway, vlan, vlan_priority, cipher_privs, mac) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    ).unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.bind(2, channel as i64).unwrap();
+    statement.bind(3, time).unwrap();
+    statement.bind(4, lan.ip_source.as_str()).unwrap();
+    statement.bind(5, lan.ip.as_str()).unwrap();
+    statement.bind(6, lan.netmask.as_str()).unwrap();
+    statement.bind(7, lan.gateway.as_str()).unwrap();
+    statement.bind(8, lan.vlan as i64).unwrap();
+    statement.bind(9, lan.vlan_priority as i64).unwrap();
+    statement.bind(10, lan.cipher_privs.as_str()).unwrap();
+    statement.bind(11, lan.mac.as_str()).unwrap();
+    statement.next().unwrap();
+}
+
+/// The last captured LAN configuration of the channel and its time
+pub fn captured(connection: &sqlite::Connection, host_id: i64, channel: u8) -> Option<(i64, Lan)> {
+    let mut statement = connection.prepare(
+        "SELECT time, ip_source, ip, netmask, gateway, vlan, vlan_priority, cipher_privs, mac FROM lan_captures WHERE host_id=? AND channel=? ORDER BY time DESC LIMIT 1"
+    ).unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.bind(2, channel as i64).unwrap();
+    match statement.next().unwrap() {
+        sqlite::State::Row => Some((statement.read::<i64>(0).unwrap(), Lan {
+            ip_source: statement.read::<String>(1).unwrap(),
+            ip: statement.read::<String>(2).unwrap(),
+            netmask: statement.read::<String>(3).unwrap(),
+            gateway: statement.read::<String>(4).unwrap(),
+            vlan: statement.read::<i64>(5).unwrap() as u16,
+            vlan_priority: statement.read::<i64>(6).unwrap() as u8,
+            cipher_privs: statement.read::<String>(7).unwrap(),
+            mac: statement.read::<String>(8).unwrap(),
+        })),
+        sqlite::State::Done => None,
+    }
+}
+
+fn print_changes(id: i64, host: &Login, changes: &[Change]) {
+    if changes.is_empty() {
+        println!("{:>04} {:<15} in sync", id, host.ip);
+    }
+    for c in changes {
+        println!("{:>04} {:<15} {:<13} {} -> {}", id, host.ip, c.setting, c.from, c.to);
+    }
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: LanCommand) {
+    let (spec, group) = match &cmd {
+        LanCommand::Capture{group, ..} => (None, group),
+        LanCommand::Diff{spec, group, ..} | LanCommand::Apply{spec, group, ..} => (Some(spec), group),
+    };
+    let spec = match spec.map(|file| Spec::load(file).map_err(|e| (file, e))) {
+        Some(Ok(spec)) => spec,
+        Some(Err((file, e))) => {
+            println!("Invalid spec {}: {}", file.display(), e);
+            return;
+        },
+        None => Spec::default(),
+    };
+    let hosts = Host::group_or_help(connection, group.tag.as_deref());
+    if hosts.is_empty() {
+        return;
+    }
+
+    match cmd {
+        LanCommand::Capture{channel, group} => {
+            let results = crate::parallel(hosts, group.jobs, |(id, host)| {
+                let lan = read(&host, channel);
+                (id, host, lan)
+            });
+            let time = crate::now();
+            for (id, host, lan) in &results {
+                match lan {
+                    Ok(lan) => {
+                        capture(connection, *id, channel, lan, time);
+                        println!("{:>04} {:<15} {} {}/{} gateway {} vlan {}", id, host.ip, lan.ip_source, lan.ip, lan.netmask, lan.gateway,
+                            if lan.vlan == 0 { String::from("off") } else { lan.vlan.to_string() });
+                    },
+                    Err(e) => println!("{:>04} {:<15} failed: {}", id, host.ip, e),
+                }
+            }
+        },
+        LanCommand::Diff{channel, ..} => {
+            for (id, host) in &hosts {
+                match captured(connection, *id, channel) {
+                    Some((time, lan)) => {
+                        println!("{:>04} {:<15} captured at {}", id, host.ip, crate::datetime(time));
+                        print_changes(*id, host, &changes(&lan, &spec.settings(&host.ip)));
+                    },
+                    None => {
+                        println!("{:>04} {:<15} not captured yet, capture it with command:", id, host.ip);
+                        println!("    ipmi.exe lan capture");
+                    },
+                }
+            }
+        },
+        LanCommand::Apply{channel, dry_run, yes, timeout, group, ..} => {
+            // the changes are of the configuration read just now, not of the last capture
+            let results = crate::parallel(hosts, group.jobs, |(id, host)| {
+                let lan = read(&host, channel);
+                (id, host, lan)
+            });
+            let time = crate::now();
+            let mut plans = Vec::new();
+            for (id, host, lan) in results {
+                match lan {
+                    Ok(lan) => {
+                        capture(connection, id, channel, &lan, time);
+                        let changes = changes(&lan, &spec.settings(&host.ip));
+                        print_changes(id, &host, &changes);
+                        for c in &changes {
+                            println!("    ipmi.exe -- lan set {} {}", channel, c.args.join(" "));
+                        }
+                        if !changes.is_empty() {
+                            plans.push((id, host, changes));
+                        }
+                    },
+                    Err(e) => println!("{:>04} {:<15} failed: {}", id, host.ip, e),
+                }
+            }
+            if dry_run || plans.is_empty() {
+                return;
+            }
+            if !yes && !crate::confirm(&format!("Apply the changes above to {} host(s)?", plans.len())) {
+                return;
+            }
+
+            let start = Instant::now();
+            let results = crate::parallel(plans, group.jobs, |(id, host, changes)| {
+                let log = |msg: &str| println!("[{:>5}s] {:>04} {:<15} {}", start.elapsed().as_secs(), id, host.ip, msg);
+                let applied = apply(&host, channel, &changes, timeout, &log);
+                // the settings as applied, to compare with the next time
+                let lan = match &applied {
+                    Applied::Done(ip) => {
+                        let ip = ip.clone().unwrap_or_else(|| host.ip.clone());
+                        read(&Login {host: Host {ip, ..host.host.clone()}, ..host.clone()}, channel).ok()
+                    },
+                    _ => None,
+                };
+                (id, host, applied, lan)
+            });
+
+            println!();
+            let time = crate::now();
+            for (id, host, applied, lan) in &results {
+                if let Some(lan) = lan {
+                    capture(connection, *id, channel, lan, time);
+                }
+                match applied {
+                    Applied::Done(Some(ip)) => match update_ip(connection, *id, ip) {
+                        Ok(_) => println!("{:>04} {:<15} applied, moved to {}", id, host.ip, ip),
+                        Err(e) => println!("{:>04} {:<15} applied, but not moved to {}: {}", id, host.ip, ip, e),
+                    },
+                    Applied::Done(None) => println!("{:>04} {:<15} applied", id, host.ip),
+                    Applied::Reverted(e) => println!("{:>04} {:<15} set back: {}", id, host.ip, e),
+                    Applied::Stranded(e) => println!("{:>04} {:<15} not answering, check it on the console: {}", id, host.ip, e),
+                }
+            }
+        },
+    }
+}
+
+#[test]
+fn lan_parse() {
+    let params = parse(&fs::read_to_string("tests/fixtures/lan_print_supermicro.txt").unwrap());
+    assert_eq!(value(&params, "MAC Address"), Some("ac:1f:6b:01:02:03"));
+    assert_eq!(value(&params, "Auth Type Enable"), Some("Callback : MD2 MD5 PASSWORD"));
+    assert_eq!(value(&params, "Bad Password Threshold"), Some("Not Available"));
+    assert_eq!(value(&params, "Serial"), None);
+
+    assert_eq!(Lan::from_params(&params), Lan {
+        ip_source: String::from("static"), ip: String::from("10.245.38.1"), netmask: String::from("255.255.255.0"),
+        gateway: String::from("10.245.38.254"), vlan: 0, vlan_priority: 0, cipher_privs: String::from("XaaaXXaaaXXaaXX"),
+        mac: String::from("ac:1f:6b:01:02:03"),
+    });
+    let lan = Lan::from_params(&parse(&fs::read_to_string("tests/fixtures/lan_print_dell.txt").unwrap()));
+    assert_eq!((lan.ip_source.as_str(), lan.vlan, lan.cipher_privs.as_str()), ("dhcp", 100, "aaaaaaaaaaaaaaa"));
+}
+
+#[test]
+fn lan_spec() {
+    let spec: Spec = serde_yaml::from_str("
+        netmask: 255.255.255.0
+        vlan: 0
+        cipher_privs: XXXaXXXXXXXXXXX
+        hosts:
+          10.245.38.2:
+            ip_source: static
+            gateway: 10.245.38.254
+    ").unwrap();
+    assert!(spec.settings.validate().is_ok());
+    assert_eq!(spec.settings("10.245.38.1").gateway, None);
+    assert_eq!(spec.settings("10.245.38.2").gateway.as_deref(), Some("10.245.38.254"));
+    assert_eq!(spec.settings("10.245.38.2").netmask.as_deref(), Some("255.255.255.0"));
+
+    let lan = Lan::from_params(&parse(&fs::read_to_string("tests/fixtures/lan_print_dell.txt").unwrap()));
+    let planned: Vec<(&str, String)> = changes(&lan, &spec.settings("10.245.38.2")).into_iter().map(|c| (c.setting, c.args.join(" "))).collect();
+    assert_eq!(planned, vec![
+        ("cipher_privs", String::from("cipher_privs XXXaXXXXXXXXXXX")),
+        ("ip_source", String::from("ipsrc static")),
+        ("netmask", String::from("netmask 255.255.255.0")),
+        ("gateway", String::from("defgw ipaddr 10.245.38.254")),
+        ("vlan", String::from("vlan id off")),
+    ]);
+    let lan = Lan::from_params(&parse(&fs::read_to_string("tests/fixtures/lan_print_supermicro.txt").unwrap()));
+    assert_eq!(changes(&lan, &spec.settings("10.245.38.1")).len(), 1);
+
+    let toml: Spec = toml::from_str("vlan = 100\n[hosts.'10.245.38.1']\nip = '10.245.38.11'\n").unwrap();
+    assert_eq!(toml.settings("10.245.38.1").ip.as_deref(), Some("10.245.38.11"));
+
+    let undo: Vec<Option<String>> = changes(&lan, &Settings {ip: Some(String::from("10.245.38.11")), vlan: Some(100), ..Default::default()})
+        .into_iter().map(|c| c.undo.map(|u| u.join(" "))).collect();
+    assert_eq!(undo, vec![Some(String::from("vlan id off")), Some(String::from("ipaddr 10.245.38.1"))]);
+    let other = Lan {ip_source: String::from("other"), cipher_privs: String::new(), ..lan};
+    let undo: Vec<Option<Vec<String>>> = changes(&other, &Settings {ip_source: Some(String::from("static")), cipher_privs: Some(String::from("XXXaXXXXXXXXXXX")), ..Default::default()})
+        .into_iter().map(|c| c.undo).collect();
+    assert_eq!(undo, vec![None, None]);
+
+    for invalid in ["ip_source: bios", "vlan: 4095", "vlan_priority: 8", "netmask: 255.255.255", "cipher_privs: XXXa"] {
+        assert!(serde_yaml::from_str::<Settings>(invalid).unwrap().validate().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn lan_update_ip() {
+    let db_name = "lan.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database
+    };
+    fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '10.245.38.1', 'admin', 'admin')").unwrap();
+        connection.execute("INSERT INTO hosts VALUES (2, 0, '10.245.38.2', 'admin', 'admin')").unwrap();
+
+        assert!(update_ip(&connection, 1, "10.245.38.11").is_ok());
+        assert!(update_ip(&connection, 2, "10.245.38.11").is_err());
+        let mut statement = connection.prepare("SELECT ip FROM hosts ORDER BY id").unwrap();
+        let mut ips = Vec::new();
+        while let sqlite::State::Row = statement.next().unwrap() {
+            ips.push(statement.read::<String>(0).unwrap());
+        }
+        assert_eq!(ips, vec!["10.245.38.11", "10.245.38.2"]);
+    }
+    fs::remove_file(&database).unwrap();
+}
@@ -9,12 +9,90 @@
   WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
 --*/
 
+// the host tests of the first version are kept as they are, in a style the later clippy lints
+#![cfg_attr(test, allow(clippy::assertions_on_constants, clippy::bool_assert_comparison, clippy::clone_on_copy,
+    clippy::explicit_counter_loop, clippy::redundant_pattern_matching, clippy::useless_format))]
+
 use std::fs;
 use std::io::prelude::*;
 use std::path;
 use structopt::StructOpt;
 use structopt::clap::ArgSettings;
-use sqlite;
+
+mod audit;
+mod bmc_user;
+mod check;
+mod discover;
+mod fru;
+mod fru_image;
+mod inventory;
+mod lan;
+mod mc;
+mod password;
+mod power;
+mod profile;
+mod rmcp;
+mod rotate;
+mod sel;
+mod sel_record;
+mod sdr;
+mod sensor;
+
+/// Schema upgrades on top of the `hosts` table, applied in order and tracked by `PRAGMA user_version`
+const SCHEMA: &[&str] = &[
+    "
+    CREATE TABLE tags (host_id INTEGER NOT NULL REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, tag VARCHAR(64) NOT NULL);
+    CREATE UNIQUE INDEX ti ON tags (host_id, tag);
+    ",
+    "
+    CREATE TABLE sequences (plan VARCHAR(256) NOT NULL, direction VARCHAR(8) NOT NULL, stage INTEGER NOT NULL);
+    CREATE UNIQUE INDEX si ON sequences (plan, direction);
+    ",
+    "
+    CREATE TABLE settings (key VARCHAR(64) PRIMARY KEY, value TEXT NOT NULL);
+    CREATE TABLE sensor_samples (host_id INTEGER NOT NULL REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, time INTEGER NOT NULL, name VARCHAR(64) NOT NULL, type VARCHAR(16) NOT NULL, unit VARCHAR(16) NOT NULL, value REAL, min REAL, max REAL, status VARCHAR(16) NOT NULL, span INTEGER NOT NULL DEFAULT 0);
+    CREATE INDEX ssi ON sensor_samples (host_id, name, time);
+    ",
+    "
+    CREATE TABLE sel_cursors (host_id INTEGER PRIMARY KEY REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, record INTEGER NOT NULL, time INTEGER, sensor TEXT NOT NULL, description TEXT NOT NULL, checked INTEGER NOT NULL);
+    CREATE TABLE sel_events (host_id INTEGER NOT NULL REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, record INTEGER NOT NULL, time INTEGER, sensor_type VARCHAR(32) NOT NULL, sensor VARCHAR(64) NOT NULL, description TEXT NOT NULL, asserted TINYINT NOT NULL, severity VARCHAR(8) NOT NULL, collected INTEGER NOT NULL);
+    CREATE INDEX sei ON sel_events (host_id, time);
+    ",
+    "
+    CREATE TABLE inventory (host_id INTEGER NOT NULL REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, device INTEGER NOT NULL, name VARCHAR(64) NOT NULL, chassis_type TEXT NOT NULL, chassis_part TEXT NOT NULL, chassis_serial TEXT NOT NULL, board_date TEXT NOT NULL, board_mfg TEXT NOT NULL, board_product TEXT NOT NULL, board_serial TEXT NOT NULL, board_part TEXT NOT NULL, product_mfg TEXT NOT NULL, product_name TEXT NOT NULL, product_part TEXT NOT NULL, product_version TEXT NOT NULL, product_serial TEXT NOT NULL, product_asset_tag TEXT NOT NULL, collected INTEGER NOT NULL);
+    CREATE UNIQUE INDEX ii ON inventory (host_id, device);
+    ",
+    "
+    CREATE TABLE inventory_snapshots (host_id INTEGER NOT NULL REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, time INTEGER NOT NULL, device INTEGER NOT NULL, name VARCHAR(64) NOT NULL, chassis_type TEXT NOT NULL, chassis_part TEXT NOT NULL, chassis_serial TEXT NOT NULL, board_date TEXT NOT NULL, board_mfg TEXT NOT NULL, board_product TEXT NOT NULL, board_serial TEXT NOT NULL, board_part TEXT NOT NULL, product_mfg TEXT NOT NULL, product_name TEXT NOT NULL, product_part TEXT NOT NULL, product_version TEXT NOT NULL, product_serial TEXT NOT NULL, product_asset_tag TEXT NOT NULL);
+    CREATE INDEX isi ON inventory_snapshots (host_id, time);
+    INSERT INTO inventory_snapshots SELECT host_id, collected, device, name, chassis_type, chassis_part, chassis_serial, board_date, board_mfg, board_product, board_serial, board_part, product_mfg, product_name, product_part, product_version, product_serial, product_asset_tag FROM inventory;
+    ",
+    "
+    CREATE TABLE details (host_id INTEGER PRIMARY KEY REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, name VARCHAR(64) NOT NULL DEFAULT '', notes TEXT NOT NULL DEFAULT '', mac VARCHAR(32) NOT NULL DEFAULT '', firmware VARCHAR(32) NOT NULL DEFAULT '');
+    ",
+    "
+    CREATE TABLE lan_captures (host_id INTEGER NOT NULL REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, channel INTEGER NOT NULL, time INTEGER NOT NULL, ip_source VARCHAR(8) NOT NULL, ip VARCHAR(64) NOT NULL, netmask VARCHAR(64) NOT NULL, gateway VARCHAR(64) NOT NULL, vlan INTEGER NOT NULL, vlan_priority INTEGER NOT NULL, cipher_privs VARCHAR(16) NOT NULL, mac VARCHAR(32) NOT NULL);
+    CREATE INDEX lci ON lan_captures (host_id, channel, time);
+    ",
+    "
+    CREATE TABLE rotations (host_id INTEGER PRIMARY KEY REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, pswd VARCHAR(64) NOT NULL, started INTEGER NOT NULL);
+    ",
+    "
+    CREATE TABLE passwords (host_id INTEGER NOT NULL REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, salt VARCHAR(32) NOT NULL, hash VARCHAR(64) NOT NULL, time INTEGER NOT NULL);
+    CREATE INDEX pi ON passwords (host_id, time);
+    ",
+    "
+    ALTER TABLE details ADD COLUMN manufacturer_id INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE details ADD COLUMN ipmi_version VARCHAR(8) NOT NULL DEFAULT '';
+    ",
+    "
+    CREATE TABLE checks (host_id INTEGER PRIMARY KEY REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, status VARCHAR(8) NOT NULL, latency REAL, checked INTEGER NOT NULL, seen INTEGER);
+    ",
+    "
+    CREATE TABLE profiles (name VARCHAR(64) PRIMARY KEY, user VARCHAR(64) NOT NULL, pswd VARCHAR(256) NOT NULL, interface VARCHAR(16) NOT NULL DEFAULT 'lanplus', cipher INTEGER);
+    CREATE TABLE host_profiles (host_id INTEGER PRIMARY KEY REFERENCES hosts(id) ON UPDATE CASCADE ON DELETE CASCADE, profile VARCHAR(64) NOT NULL REFERENCES profiles(name) ON UPDATE CASCADE);
+    ",
+];
 
 #[derive(StructOpt, Debug)]
 /// Hosts management tool and ipmitool wrapper
@@ -27,23 +105,113 @@ struct Opts {
     ipmitool_args: Vec<String>,
 }
 
+impl Opts {
+    /// The options of the command line, with the args after `--` given to ipmitool as they are, which clap takes for a
+    /// mistyped subcommand if one is close to the name of a subcommand, e.g. `sel` or `lanplus`
+    fn from_command_line<I: IntoIterator<Item = std::ffi::OsString>>(args: I) -> Opts {
+        let mut args: Vec<std::ffi::OsString> = args.into_iter().collect();
+        let ipmitool_args = match args.iter().position(|i| i == "--") {
+            Some(i) => args.split_off(i).iter().skip(1).map(|i| i.to_string_lossy().into_owned()).collect(),
+            None => Vec::new(),
+        };
+        Opts {ipmitool_args, ..Opts::from_iter(args)}
+    }
+}
+
 #[derive(StructOpt, Debug)]
 enum Command {
     /// Host management subcommand(s)
     Host {
         #[structopt(subcommand)]
         cmd: HostCommand,
-    }
+    },
+    /// Chassis power subcommand(s)
+    Power {
+        #[structopt(subcommand)]
+        cmd: power::PowerCommand,
+    },
+    /// List the sensor readings and thresholds
+    Sensors {
+        #[structopt(subcommand)]
+        cmd: Option<sensor::SensorCommand>,
+        #[structopt(flatten)]
+        opts: sensor::SensorsOpts,
+    },
+    /// System Event Log subcommand(s)
+    Sel {
+        #[structopt(subcommand)]
+        cmd: sel::SelCommand,
+    },
+    /// FRU inventory subcommand(s)
+    Fru {
+        #[structopt(subcommand)]
+        cmd: fru::FruCommand,
+    },
+    /// FRU inventory history subcommand(s)
+    Inventory {
+        #[structopt(subcommand)]
+        cmd: inventory::InventoryCommand,
+    },
+    /// BMC LAN configuration subcommand(s)
+    Lan {
+        #[structopt(subcommand)]
+        cmd: lan::LanCommand,
+    },
+    /// Sensor Data Record cache subcommand(s)
+    Sdr {
+        #[structopt(subcommand)]
+        cmd: sdr::SdrCommand,
+    },
+    /// BMC local user subcommand(s)
+    BmcUser {
+        #[structopt(subcommand)]
+        cmd: bmc_user::BmcUserCommand,
+    },
+    /// Audit the security settings of the BMCs
+    Audit {
+        #[structopt(flatten)]
+        opts: audit::AuditOpts,
+    },
+    /// Find the BMCs in a network by RMCP presence ping, and add them as hosts
+    Discover {
+        #[structopt(flatten)]
+        opts: discover::DiscoverOpts,
+    },
 }
 
 #[derive(StructOpt, Debug)]
 enum HostCommand {
     /// List all IPMI hosts
-    List,
+    List {
+        /// The columns to list instead of the default ones, e.g. ip,product,serial
+        #[structopt(short, long, use_delimiter = true, possible_values = COLUMNS)]
+        columns: Vec<String>,
+        /// Only the hosts with the password set longer than <stale> ago or not known when, e.g. 90d
+        #[structopt(long, parse(try_from_str = seconds))]
+        stale: Option<u64>,
+    },
     /// Add an IPMI host record
     Add {
         #[structopt(flatten)]
         host: Host,
+        /// Try the default credentials (see `host defaults`) in order, and add the host with the first that logs in
+        #[structopt(long)]
+        probe_defaults: bool,
+        /// The time to wait between the credentials tried, not to lock the BMC users out, e.g. 5s
+        #[structopt(long, default_value = "5s", parse(try_from_str = seconds))]
+        delay: u64,
+        /// Log in with Get Device ID first, and add the host only if it works
+        #[structopt(long)]
+        verify: bool,
+        /// Read the password from the terminal instead of -p
+        #[structopt(long, conflicts_with = "pswd")]
+        password_prompt: bool,
+        /// The name of the host, or of the hosts of a range with {n} for the number in it from 1, e.g. r12n{n:02}
+        #[structopt(short, long, parse(try_from_str = name_template))]
+        name: Option<String>,
+        /// The credential profile of the host, to inherit the user and password of unless given
+        #[structopt(long, conflicts_with = "probe-defaults")]
+        profile: Option<String>,
     },
     /// Delete an IPMI host record
     Del {
@@ -52,29 +220,297 @@ enum HostCommand {
     /// Set current IPMI host
     Use {
         id: i64,
+    },
+    /// Tag an IPMI host, tagged hosts can be worked on as a group
+    Tag {
+        id: i64,
+        tags: Vec<String>,
+        /// Remove the tags instead of adding them
+        #[structopt(short, long)]
+        remove: bool,
+    },
+    /// Set the name or notes of an IPMI host
+    Edit {
+        id: i64,
+        #[structopt(short, long)]
+        name: Option<String>,
+        #[structopt(long)]
+        notes: Option<String>,
+    },
+    /// Find the IPMI hosts by IP, name, notes, MAC, firmware, serial or product
+    Find {
+        term: String,
+    },
+    /// Set a new password of the user of the host(s) on the BMC, and on the host once it logs in
+    Rotate {
+        #[structopt(flatten)]
+        opts: rotate::RotateOpts,
+    },
+    /// Check if the BMCs answer an RMCP presence ping on UDP 623, without logging in
+    Check {
+        #[structopt(flatten)]
+        opts: check::CheckOpts,
+    },
+    /// Credential profile subcommand(s)
+    Profile {
+        #[structopt(subcommand)]
+        cmd: profile::ProfileCommand,
+    },
+    /// Show or set the credentials `host add --probe-defaults` tries
+    Defaults {
+        /// The user names and passwords in order, e.g. ADMIN:ADMIN,root:calvin
+        #[structopt(use_delimiter = true, parse(try_from_str = credential))]
+        credentials: Vec<(String, String)>,
+    },
+    /// Show or set the password policy of `host rotate`
+    Policy {
+        /// The length of the generated passwords, at most 20
+        #[structopt(long)]
+        length: Option<usize>,
+        /// The min length of the given passwords
+        #[structopt(long)]
+        min_length: Option<usize>,
+        /// The symbols in the generated passwords, some of +-.:=@_ or "" for none
+        #[structopt(long)]
+        symbols: Option<String>,
+        /// The number of the last passwords of a host not to reuse, 0 for all of them
+        #[structopt(long)]
+        history: Option<usize>,
+    },
+}
+
+/// The columns of `host list --columns`, the inventory ones are of the builtin FRU device
+const COLUMNS: &[&str] = &["id", "ip", "user", "tags", "name", "notes", "mac", "firmware", "manufacturer", "product", "serial", "board", "part", "asset_tag", "pswd_age", "manufacturer_id", "ipmi_version", "status", "profile"];
+
+#[derive(StructOpt, Debug)]
+struct Group {
+    /// Work on all hosts with the tag instead of the current host
+    #[structopt(short, long)]
+    tag: Option<String>,
+    /// Max number of hosts to work on in parallel
+    #[structopt(short, long, default_value = "8")]
+    jobs: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json"  => Ok(Format::Json),
+            "csv"   => Ok(Format::Csv),
+            _       => Err(format!("invalid format {}, expect one of table, json or csv", s)),
+        }
+    }
+}
+
+/// Quote a CSV field if needed
+fn csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+/// The symbols kept as they are by the shell, and by cmd on Windows, without quotes
+const SHELL_SAFE: &str = "+-.:=@_";
+
+/// Quote an argument of the ipmitool command line run through the shell if needed
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || SHELL_SAFE.contains(c)) {
+        String::from(arg)
+    } else if cfg!(target_os = "windows") {
+        format!("\"{}\"", arg)
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
     }
 }
 
-#[derive(StructOpt,Debug)]
+/// Case insensitive glob match, `*` for any characters and `?` for any one character
+fn glob(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut i, mut j) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while j < t.len() {
+        if i < p.len() && (p[i] == '?' || p[i] == t[j]) {
+            i += 1;
+            j += 1;
+        } else if i < p.len() && p[i] == '*' {
+            star = Some((i, j));
+            i += 1;
+        } else if let Some((si, sj)) = star {
+            i = si + 1;
+            j = sj + 1;
+            star = Some((si, sj + 1));
+        } else {
+            return false;
+        }
+    }
+    p[i..].iter().all(|c| *c == '*')
+}
+
+/// Parse a duration like 90s, 30m, 24h, 7d or 2w into seconds
+fn seconds(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (n, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n = n.parse::<u64>().map_err(|_| format!("invalid duration {}, expect e.g. 30m, 24h or 7d", s))?;
+    match unit {
+        "" | "s" => Ok(n),
+        "m"      => Ok(n * 60),
+        "h"      => Ok(n * 60 * 60),
+        "d"      => Ok(n * 60 * 60 * 24),
+        "w"      => Ok(n * 60 * 60 * 24 * 7),
+        _        => Err(format!("invalid duration {}, expect e.g. 30m, 24h or 7d", s)),
+    }
+}
+
+/// Seconds since the unix epoch
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Local date and time of the unix timestamp for tables, e.g. 2020-06-01 08:00:00
+fn datetime(t: i64) -> String {
+    use chrono::TimeZone;
+    chrono::Local.timestamp_opt(t, 0).single().map_or(t.to_string(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// RFC 3339 date and time of the unix timestamp for exports, e.g. 2020-06-01T08:00:00+08:00
+fn rfc3339(t: i64) -> String {
+    use chrono::TimeZone;
+    chrono::Local.timestamp_opt(t, 0).single().map_or(t.to_string(), |t| t.to_rfc3339())
+}
+
+fn datahome() -> path::PathBuf {
+    let datahome = if cfg!(target_os = "windows") { std::env::var("USERPROFILE").unwrap() } else { std::env::var("HOME").unwrap() };
+    path::PathBuf::from(datahome)
+}
+
+/// The directory for the files kept besides the database, e.g. SEL backups, created if needed
+fn datadir(sub: &str) -> path::PathBuf {
+    let dir = datahome().join(".ipmi").join(sub);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Ask on the terminal, `true` if answered with y or yes
+fn confirm(prompt: &str) -> bool {
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).unwrap_or(0);
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn setting(connection: &sqlite::Connection, key: &str) -> Option<String> {
+    let mut statement = connection.prepare("SELECT value FROM settings WHERE key=?").unwrap();
+    statement.bind(1, key).unwrap();
+    match statement.next().unwrap() {
+        sqlite::State::Row => Some(statement.read::<String>(0).unwrap()),
+        sqlite::State::Done => None,
+    }
+}
+
+fn set_setting(connection: &sqlite::Connection, key: &str, value: &str) {
+    let mut statement = connection.prepare("INSERT OR REPLACE INTO settings(key, value) VALUES (?, ?)").unwrap();
+    statement.bind(1, key).unwrap();
+    statement.bind(2, value).unwrap();
+    statement.next().unwrap();
+}
+
+#[derive(StructOpt,Debug,Clone,Default)]
 struct Host {
-    /// Host IP
+    /// Host IP, or for `host add` a range or network of them, e.g. 10.245.38.1-10.245.38.40 or 10.245.38.0/26
     #[structopt(short, long)]
     ip: String,
     /// Host user name
-    #[structopt(short, long)]
+    #[structopt(short, long, default_value = "UNKNOWN", hide_default_value = true)]
     user: String,
     /// Host user password
-    #[structopt(short, long)]
+    #[structopt(short, long, default_value = "UNKNOWN", hide_default_value = true)]
     pswd: String,
 }
 
+/// The host as ipmitool logs in to it, with the interface and cipher suite of its profile if it has one
+#[derive(Debug,Clone,Default)]
+struct Login {
+    host: Host,
+    /// The ipmitool interface of the profile of the host, lanplus if none
+    interface: Option<String>,
+    /// The cipher suite ID of the profile of the host, the ipmitool default if none
+    cipher: Option<u8>,
+}
+
+impl From<Host> for Login {
+    fn from(host: Host) -> Login {
+        Login {host, ..Default::default()}
+    }
+}
+
+impl std::ops::Deref for Login {
+    type Target = Host;
+    fn deref(&self) -> &Host {
+        &self.host
+    }
+}
+
+/// A user name and password as `user:pswd`
+fn credential(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((user, pswd)) if !user.is_empty() && !pswd.is_empty() => Ok((String::from(user), String::from(pswd))),
+        _ => Err(format!("invalid credential {}, expect <user>:<pswd>", s)),
+    }
+}
+
+/// The name of the n-th host of a range, with {n} or e.g. {n:02} in the template for n
+fn host_name(template: &str, n: usize) -> Result<String, String> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').map(|end| start + end).ok_or(format!("invalid name template {}, unclosed {{", template))?;
+        name.push_str(&rest[..start]);
+        match rest[start + 1..end].split_once(':') {
+            None if &rest[start + 1..end] == "n" => name.push_str(&n.to_string()),
+            Some(("n", width)) if width.starts_with('0') && width.parse::<usize>().is_ok() => {
+                name.push_str(&format!("{:0w$}", n, w = width.parse::<usize>().unwrap()));
+            },
+            Some(("n", width)) if width.parse::<usize>().is_ok() => name.push_str(&format!("{:w$}", n, w = width.parse::<usize>().unwrap())),
+            _ => return Err(format!("invalid name template {}, expect {{n}} or e.g. {{n:02}}", template)),
+        }
+        rest = &rest[end + 1..];
+    }
+    name.push_str(rest);
+    Ok(name)
+}
+
+/// Check a name template of `host add --name`
+fn name_template(s: &str) -> Result<String, String> {
+    host_name(s, 1).map(|_| String::from(s))
+}
+
+/// The credentials to try on new hosts, the vendor defaults unless set by `host defaults`
+fn default_credentials(connection: &sqlite::Connection) -> Vec<(String, String)> {
+    setting(connection, "probe.credentials")
+        .map(|v| v.split(',').filter_map(|c| credential(c).ok()).collect())
+        .unwrap_or_else(|| audit::DEFAULT_CREDENTIALS.iter().map(|(user, pswd)| (user.to_string(), pswd.to_string())).collect())
+}
+
 impl Host {
     fn init(db: Option<&str>) -> (sqlite::Connection, path::PathBuf) {
-        let datahome = if cfg!(target_os = "windows") { std::env::var("USERPROFILE").unwrap() } else { std::env::var("HOME").unwrap() };
-        let database = path::Path::new(&datahome).join(db.unwrap_or(".ipmi.db"));
+        let database = datahome().join(db.unwrap_or(".ipmi.db"));
         let _1st_run = !database.is_file();
 
-        let mut f = fs::OpenOptions::new().read(true).write(true).create(true).open(&database).unwrap();
+        let mut f = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&database).unwrap();
         f.flush().unwrap();
 
         let connection = sqlite::open(&database).unwrap();
@@ -86,29 +522,106 @@ impl Host {
                 "
             ).unwrap();
         }
+        connection.execute("PRAGMA foreign_keys = ON").unwrap();
+        let version = {
+            let mut statement = connection.prepare("PRAGMA user_version").unwrap();
+            statement.next().unwrap();
+            statement.read::<i64>(0).unwrap() as usize
+        };
+        for (i, sql) in SCHEMA.iter().enumerate().skip(version) {
+            connection.execute(format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;", sql, i + 1)).unwrap();
+        }
         (connection, database)
     }
+    /// If the IP is valid, IPv4 zero padded too as the hosts are kept, e.g. 200.050.005.000, which the std parser
+    /// rejects since Rust 1.55
+    fn ip_ok(ip: &str) -> bool {
+        let octets: Vec<&str> = ip.split('.').collect();
+        if octets.len() == 4 && octets.iter().all(|o| !o.is_empty() && o.len() <= 3 && o.chars().all(|c| c.is_ascii_digit())) {
+            return octets.iter().all(|o| o.parse::<u16>().unwrap() <= 255);
+        }
+        ip.parse::<std::net::Ipv6Addr>().is_ok()
+    }
     fn list(connection: &sqlite::Connection) -> bool {
         let mut list_some = false;
-        let mut statement = connection.prepare("SELECT id, df, ip, user FROM hosts ORDER BY ROWID ASC").unwrap();
-        println!("---------------------------------------------");
-        println!("Index        IP                          User");
-        println!("-----        --                          ----");
+        let mut statement = connection.prepare(format!(
+            "SELECT id, df, ip, {}, IFNULL((SELECT group_concat(tag, ',') FROM tags WHERE host_id=hosts.id), ''), {} FROM hosts ORDER BY ROWID ASC", profile::USER, check::STATUS
+        )).unwrap();
+        println!("--------------------------------------------------------------------------");
+        println!("Index        IP                          User    Status       Tags");
+        println!("-----        --                          ----    ------       ----");
         while let sqlite::State::Row = statement.next().unwrap() {
             let id   = statement.read::<i64>(0).unwrap();
             let df   = statement.read::<i64>(1).unwrap();
             let ip   = statement.read::<String>(2).unwrap();
             let user = statement.read::<String>(3).unwrap();
+            let tags = statement.read::<String>(4).unwrap();
+            let status = statement.read::<String>(5).unwrap();
 
             let df_mark = if df != 0  { '*' } else { ' ' };
-            println!("{}{:>04}        {:<15}  {:>15}", df_mark, id, ip, user);
+            println!("{}{:>04}        {:<15}  {:>15}    {:<12} {}", df_mark, id, ip, user, status, tags);
 
             list_some = true;
         }
-        println!("---------------------------------------------");
+        println!("--------------------------------------------------------------------------");
 
         list_some
     }
+    /// List the hosts with the columns of `COLUMNS`, each as wide as its widest value
+    fn list_columns(connection: &sqlite::Connection, columns: &[String]) -> bool {
+        Host::table(connection, columns, None)
+    }
+    fn table(connection: &sqlite::Connection, columns: &[String], ids: Option<&[i64]>) -> bool {
+        let details = |column: &str| format!("IFNULL((SELECT {} FROM details WHERE host_id=hosts.id), '')", column);
+        let inventory = |expr: &str| format!("IFNULL((SELECT {} FROM inventory WHERE host_id=hosts.id AND device=0), '')", expr);
+        let exprs: Vec<String> = columns.iter().map(|c| match c.as_str() {
+            "id"              => String::from("printf('%04d', id)"),
+            "ip"              => String::from("ip"),
+            "user"            => String::from(profile::USER),
+            "profile"         => String::from("IFNULL((SELECT profile FROM host_profiles WHERE host_id=hosts.id), '')"),
+            "tags"            => String::from("IFNULL((SELECT group_concat(tag, ',') FROM tags WHERE host_id=hosts.id), '')"),
+            "name"            => details("name"),
+            "notes"           => details("notes"),
+            "mac"             => details("mac"),
+            "firmware"        => details("firmware"),
+            "manufacturer_id" => details("CAST(NULLIF(manufacturer_id, 0) AS TEXT)"),
+            "ipmi_version"    => details("ipmi_version"),
+            "manufacturer"    => inventory("product_mfg"),
+            "product"         => inventory("product_name"),
+            "serial"          => inventory("COALESCE(NULLIF(product_serial, ''), NULLIF(chassis_serial, ''), board_serial)"),
+            "board"           => inventory("board_product"),
+            "part"            => inventory("board_part"),
+            "asset_tag"       => inventory("product_asset_tag"),
+            "pswd_age"        => String::from("IFNULL((SELECT ((strftime('%s', 'now') - MAX(time)) / 86400) || 'd' FROM passwords WHERE host_id=hosts.id), '')"),
+            "status"          => String::from(check::STATUS),
+            _                 => String::from("''"),
+        }).collect();
+        let filter = match ids {
+            Some(ids) => format!("WHERE id IN ({})", ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ")),
+            None => String::new(),
+        };
+        let mut statement = connection.prepare(format!("SELECT df, {} FROM hosts {} ORDER BY ROWID ASC", exprs.join(", "), filter)).unwrap();
+        let mut rows: Vec<(bool, Vec<String>)> = Vec::new();
+        while let sqlite::State::Row = statement.next().unwrap() {
+            let df = statement.read::<i64>(0).unwrap() != 0;
+            rows.push((df, (1..=columns.len()).map(|i| statement.read::<String>(i).unwrap()).collect()));
+        }
+
+        let widths: Vec<usize> = columns.iter().enumerate()
+            .map(|(i, c)| rows.iter().map(|(_, r)| r[i].chars().count()).max().unwrap_or(0).max(c.len()))
+            .collect();
+        let line = |cells: Vec<String>| cells.iter().zip(&widths).map(|(c, w)| format!("{:<w$}", c, w = w)).collect::<Vec<_>>().join("    ").trim_end().to_string();
+        let total = widths.iter().sum::<usize>() + 4 * widths.len().saturating_sub(1) + 1;
+        println!("{}", "-".repeat(total));
+        println!(" {}", line(columns.iter().map(|c| c.to_uppercase()).collect()));
+        println!(" {}", line(columns.iter().map(|c| "-".repeat(c.len())).collect()));
+        for (df, row) in &rows {
+            println!("{}{}", if *df { '*' } else { ' ' }, line(row.clone()));
+        }
+        println!("{}", "-".repeat(total));
+
+        !rows.is_empty()
+    }
     fn set(connection: &sqlite::Connection, id: i64) -> bool {
         let mut id_ok = false;
         let mut statement = connection.prepare(format!("SELECT id FROM hosts WHERE id={}", id)).unwrap();
@@ -121,7 +634,7 @@ impl Host {
         }
 
         if id_ok {
-            let mut statement = connection.prepare(format!("SELECT id FROM hosts WHERE df=1")).unwrap();
+            let mut statement = connection.prepare("SELECT id FROM hosts WHERE df=1").unwrap();
             while let sqlite::State::Row = statement.next().unwrap() {
                 connection.execute(format!("UPDATE hosts SET df=0 WHERE id={}", statement.read::<i64>(0).unwrap())).unwrap();
             }
@@ -132,51 +645,226 @@ impl Host {
 
         id_ok
     }
-    fn get(connection: &sqlite::Connection) -> Option<Host> {
-        let mut host: Option<Host> = None;
+    fn tag(connection: &sqlite::Connection, id: i64, tags: &[String], remove: bool) -> bool {
+        let mut id_ok = false;
+        let mut statement = connection.prepare(format!("SELECT id FROM hosts WHERE id={}", id)).unwrap();
+        while let sqlite::State::Row = statement.next().unwrap() {
+            id_ok = true;
+        }
 
-        let mut statement = connection.prepare(format!("SELECT ip, user, pswd FROM hosts WHERE df=1")).unwrap();
+        if id_ok {
+            for tag in tags {
+                let mut statement = if remove {
+                    connection.prepare("DELETE FROM tags WHERE host_id=? AND tag=?").unwrap()
+                } else {
+                    connection.prepare("INSERT OR IGNORE INTO tags(host_id, tag) VALUES (?, ?)").unwrap()
+                };
+                statement.bind(1, id).unwrap();
+                statement.bind(2, tag.as_str()).unwrap();
+                statement.next().unwrap();
+            }
+        }
+
+        id_ok
+    }
+    fn edit(connection: &sqlite::Connection, id: i64, name: Option<&str>, notes: Option<&str>) -> bool {
+        if !Host::exists(connection, id) {
+            return false;
+        }
+        let mut statement = connection.prepare("INSERT OR IGNORE INTO details(host_id) VALUES (?)").unwrap();
+        statement.bind(1, id).unwrap();
+        statement.next().unwrap();
+        for (column, value) in [("name", name), ("notes", notes)] {
+            if let Some(value) = value {
+                let mut statement = connection.prepare(format!("UPDATE details SET {}=? WHERE host_id=?", column)).unwrap();
+                statement.bind(1, value).unwrap();
+                statement.bind(2, id).unwrap();
+                statement.next().unwrap();
+            }
+        }
+        true
+    }
+    fn exists(connection: &sqlite::Connection, id: i64) -> bool {
+        let mut statement = connection.prepare("SELECT id FROM hosts WHERE id=?").unwrap();
+        statement.bind(1, id).unwrap();
+        matches!(statement.next().unwrap(), sqlite::State::Row)
+    }
+    /// The hosts with the term in the IP, details or inventory, by full-text search if the sqlite has FTS5
+    fn find(connection: &sqlite::Connection, term: &str) -> Vec<i64> {
+        let text = "
+            SELECT id, ip || ' ' || IFNULL(name, '') || ' ' || IFNULL(notes, '') || ' ' || IFNULL(mac, '') || ' ' || IFNULL(firmware, '') || ' ' ||
+                IFNULL((SELECT group_concat(chassis_serial || ' ' || board_serial || ' ' || product_serial || ' ' || board_product || ' ' || board_part || ' ' ||
+                    product_name || ' ' || product_part || ' ' || product_asset_tag, ' ') FROM inventory WHERE host_id=hosts.id), '') AS text
+            FROM hosts LEFT JOIN details ON details.host_id=hosts.id
+        ";
+        let fts = connection.execute(format!(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS temp.host_search USING fts5(id UNINDEXED, text); DELETE FROM temp.host_search; INSERT INTO temp.host_search {}", text
+        ));
+        let mut statement = match fts {
+            Ok(_) => {
+                let mut statement = connection.prepare("SELECT id FROM temp.host_search WHERE host_search MATCH ? ORDER BY rank").unwrap();
+                statement.bind(1, format!("\"{}\"*", term.replace('"', "\"\"")).as_str()).unwrap();
+                statement
+            },
+            Err(_) => {
+                let mut statement = connection.prepare(format!("SELECT id FROM ({}) WHERE text LIKE '%' || ? || '%' ORDER BY id", text)).unwrap();
+                statement.bind(1, term).unwrap();
+                statement
+            },
+        };
+        let mut ids = Vec::new();
         while let sqlite::State::Row = statement.next().unwrap() {
-            let ip   = statement.read::<String>(0).unwrap();
-            let user = statement.read::<String>(1).unwrap();
-            let pswd = statement.read::<String>(2).unwrap();
-            host = Some(Host {ip, user, pswd});
+            ids.push(statement.read::<i64>(0).unwrap());
         }
-        host
+        ids
     }
-    fn add(connection: &sqlite::Connection, host: &Host) {
-        if host.ip != "UNKNOWN" && host.user != "UNKNOWN" && host.pswd != "UNKNOWN" {
-            let mut ip_ok = false;
-            if let Ok(_) = host.ip.parse::<std::net::Ipv4Addr>() {
-                ip_ok = true;
+    /// The hosts of the condition with their own user and password, or else the ones of their profile
+    fn select(connection: &sqlite::Connection, condition: &str, tag: Option<&str>) -> Vec<(i64, Login)> {
+        let mut statement = connection.prepare(format!(
+            "SELECT hosts.id, ip, hosts.user, hosts.pswd, profiles.name, profiles.user, profiles.pswd, profiles.interface, profiles.cipher
+             FROM hosts LEFT JOIN host_profiles ON host_profiles.host_id=hosts.id LEFT JOIN profiles ON profiles.name=host_profiles.profile
+             WHERE {} ORDER BY hosts.ROWID ASC", condition
+        )).unwrap();
+        if let Some(tag) = tag {
+            statement.bind(1, tag).unwrap();
+        }
+        let mut hosts = Vec::new();
+        while let sqlite::State::Row = statement.next().unwrap() {
+            let id   = statement.read::<i64>(0).unwrap();
+            let ip   = statement.read::<String>(1).unwrap();
+            let user = statement.read::<String>(2).unwrap();
+            let pswd = statement.read::<String>(3).unwrap();
+            let host = Host {ip, user, pswd};
+            let host = match statement.read::<Option<String>>(4).unwrap() {
+                Some(name) => {
+                    let profile = profile::Profile {
+                        name,
+                        user: statement.read::<String>(5).unwrap(),
+                        pswd: statement.read::<String>(6).unwrap(),
+                        interface: statement.read::<String>(7).unwrap(),
+                        cipher: statement.read::<Option<i64>>(8).unwrap().map(|c| c as u8),
+                    };
+                    // the host is still selected if the password of the profile cannot be got, to fail to log in with none
+                    profile.inherit(&host).unwrap_or_else(|e| {
+                        println!("Failed to get the password of profile {}: {}", profile.name, e);
+                        profile::Profile {pswd: String::new(), ..profile.clone()}.inherit(&host).unwrap()
+                    })
+                },
+                None => Login::from(host),
+            };
+            hosts.push((id, host));
+        }
+        hosts
+    }
+    /// The hosts tagged with `tag`, or the current host if no tag is given
+    fn group(connection: &sqlite::Connection, tag: Option<&str>) -> Vec<(i64, Login)> {
+        match tag {
+            Some(tag) => Host::select(connection, "hosts.id IN (SELECT host_id FROM tags WHERE tag=?)", Some(tag)),
+            None => Host::select(connection, "df=1", None),
+        }
+    }
+    /// All hosts, in the order of `host list`
+    fn all(connection: &sqlite::Connection) -> Vec<(i64, Login)> {
+        Host::select(connection, "1", None)
+    }
+    /// Same as `group`, and tells how to set the current host or tag hosts if there is none
+    fn group_or_help(connection: &sqlite::Connection, tag: Option<&str>) -> Vec<(i64, Login)> {
+        let hosts = Host::group(connection, tag);
+        if hosts.is_empty() {
+            println!("Please set default host or tag hosts with command:");
+            println!("    ipmi.exe host use <index of host>");
+            println!("    ipmi.exe host tag <index of host> <tag>");
+        }
+        hosts
+    }
+    fn get(connection: &sqlite::Connection) -> Option<Login> {
+        Host::select(connection, "df=1", None).pop().map(|(_, host)| host)
+    }
+    fn by_id(connection: &sqlite::Connection, id: i64) -> Option<Login> {
+        Host::select(connection, &format!("hosts.id={}", id), None).pop().map(|(_, host)| host)
+    }
+    /// The host at the IP with the first of the credentials that logs in, trying them one at a time
+    fn probe(ip: &str, credentials: &[(String, String)], delay: u64) -> Option<(Host, mc::DeviceId)> {
+        for (i, (user, pswd)) in credentials.iter().enumerate() {
+            if i > 0 {
+                std::thread::sleep(std::time::Duration::from_secs(delay));
             }
-            if let Ok(_) = host.ip.parse::<std::net::Ipv6Addr>() {
-                ip_ok = true;
+            let host = Host {ip: String::from(ip), user: user.clone(), pswd: pswd.clone()};
+            match mc::device_id(&Login::from(host.clone())) {
+                Ok(device) => return Some((host, device)),
+                Err(e) => println!("{:<15} {:<15} failed: {}", ip, user, e),
             }
-            if !ip_ok {
-                println!("Invalid IP: {}", host.ip);
-                return;
+        }
+        None
+    }
+    /// Add the hosts with their names, device IDs and profile in one transaction, and tell which are created rather than updated
+    fn add_all(connection: &sqlite::Connection, hosts: &[(Host, Option<String>, Option<mc::DeviceId>)], profile: Option<&str>) -> Vec<(i64, bool)> {
+        connection.execute("BEGIN").unwrap();
+        let mut inserted = Vec::new();
+        for (host, name, device) in hosts {
+            let created = Host::id(connection, host).is_none();
+            if Host::insert(connection, host) {
+                inserted.push((host, name, device, created));
             }
-
-            connection.execute(
-                format!(
-                    "INSERT INTO hosts(ip, user, pswd) VALUES ('{}', '{}', '{}') ON CONFLICT(ip, user) DO UPDATE SET pswd=excluded.pswd;"
-                , host.ip, host.user, host.pswd)
-            ).unwrap();
-            let mut _id_ = 0;
-            let mut statement = connection.prepare("SELECT id FROM hosts ORDER BY ROWID ASC").unwrap();
-            while let sqlite::State::Row = statement.next().unwrap() {
-                _id_ += 1;
-                let id = statement.read::<i64>(0).unwrap();
-                if _id_ < id {
-                    connection.execute(format!("UPDATE hosts SET id= {} WHERE id={}", _id_, id)).unwrap();
-                }
+        }
+        // the indexes are final once renumbered, after all the hosts are in
+        Host::renumber(connection);
+        let mut added = Vec::new();
+        for (host, name, device, created) in inserted {
+            let id = Host::id(connection, host).unwrap();
+            if name.is_some() {
+                Host::edit(connection, id, name.as_deref(), None);
+            }
+            if let Some(device) = device {
+                mc::store(connection, id, device);
             }
-            connection.execute(format!("UPDATE sqlite_sequence SET seq={} WHERE name='hosts'", _id_)).unwrap();
+            if let Some(profile) = profile {
+                profile::attach(connection, id, profile, false).unwrap();
+            }
+            added.push((id, created));
         }
+        connection.execute("COMMIT").unwrap();
+        added
     }
-    fn del(connection: &sqlite::Connection, id: i64) {
-        connection.execute(format!("DELETE FROM hosts WHERE id={}", id)).unwrap();
+    /// Add the host, or update the password of the host with the IP and user, and return the index of the host
+    fn add(connection: &sqlite::Connection, host: &Host) -> Option<i64> {
+        Host::add_all(connection, &[(host.clone(), None, None)], None).pop().map(|(id, _)| id)
+    }
+    /// The index of the host with the IP and user
+    fn id(connection: &sqlite::Connection, host: &Host) -> Option<i64> {
+        let mut statement = connection.prepare("SELECT id FROM hosts WHERE ip=? AND user=?").unwrap();
+        statement.bind(1, host.ip.as_str()).unwrap();
+        statement.bind(2, host.user.as_str()).unwrap();
+        match statement.next().unwrap() {
+            sqlite::State::Row => Some(statement.read::<i64>(0).unwrap()),
+            sqlite::State::Done => None,
+        }
+    }
+    /// Insert the host, or update the password of the host with the IP and user, without renumbering the hosts
+    fn insert(connection: &sqlite::Connection, host: &Host) -> bool {
+        if host.ip == "UNKNOWN" || host.user == "UNKNOWN" || host.pswd == "UNKNOWN" {
+            return false;
+        }
+        if !Host::ip_ok(&host.ip) {
+            println!("Invalid IP: {}", host.ip);
+            return false;
+        }
+
+        let mut statement = connection.prepare(
+            "INSERT INTO hosts(ip, user, pswd) VALUES (?, ?, ?) ON CONFLICT(ip, user) DO UPDATE SET pswd=excluded.pswd"
+        ).unwrap();
+        statement.bind(1, host.ip.as_str()).unwrap();
+        statement.bind(2, host.user.as_str()).unwrap();
+        statement.bind(3, host.pswd.as_str()).unwrap();
+        statement.next().unwrap();
+        // no history of the passwords inherited from a profile
+        if !host.pswd.is_empty() {
+            password::record(connection, Host::id(connection, host).unwrap(), &host.pswd, now());
+        }
+        true
+    }
+    /// Number the hosts from 1 without gaps, in the order they are listed
+    fn renumber(connection: &sqlite::Connection) {
         let mut _id_ = 0;
         let mut statement = connection.prepare("SELECT id FROM hosts ORDER BY ROWID ASC").unwrap();
         while let sqlite::State::Row = statement.next().unwrap() {
@@ -188,18 +876,22 @@ impl Host {
         }
         connection.execute(format!("UPDATE sqlite_sequence SET seq={} WHERE name='hosts'", _id_)).unwrap();
     }
+    fn del(connection: &sqlite::Connection, id: i64) {
+        connection.execute(format!("DELETE FROM hosts WHERE id={}", id)).unwrap();
+        Host::renumber(connection);
+    }
     fn with_args(&self, opt: &Opts) -> String {
-        if opt.ipmitool_args.len() == 0 {
-            return String::from("");
-        }
-        let mut ipmitool_args = format!("ipmitool");
+        self.with_ipmitool_args(&opt.ipmitool_args)
+    }
+    fn with_ipmitool_args(&self, args: &[String]) -> String {
+        let mut ipmitool_args = String::from("ipmitool");
 
         let mut ipmitool_host = String::new();
         let mut option_i = false;
         let mut option_h = false;
         let mut option_u = false;
         let mut option_p = false;
-        for i in &opt.ipmitool_args {
+        for i in args {
             match i.as_str() {
                 "-I" => option_i = true,
                 "-H" => option_h = true,
@@ -215,16 +907,16 @@ impl Host {
             ipmitool_host.push_str(&format!(" -H {}", &self.ip));
         }
         if !option_u {
-            ipmitool_host.push_str(&format!(" -U {}", &self.user));
+            ipmitool_host.push_str(&format!(" -U {}", quote(&self.user)));
         }
         if !option_p {
-            ipmitool_host.push_str(&format!(" -P {}", &self.pswd));
+            ipmitool_host.push_str(&format!(" -P {}", quote(&self.pswd)));
         }
 
         let mut ipmitool_rest = String::new();
-        for i in &opt.ipmitool_args {
+        for i in args {
             ipmitool_rest.push(' ');
-            ipmitool_rest.push_str(&i);
+            ipmitool_rest.push_str(i);
         }
         ipmitool_args.push_str(&ipmitool_host);
         ipmitool_args.push_str(&ipmitool_rest);
@@ -233,16 +925,100 @@ impl Host {
     }
 }
 
+impl Login {
+    /// The ipmitool args with the interface and cipher suite of the profile and the SDR cache of the host, unless given
+    fn args(&self, args: &[String]) -> Vec<String> {
+        let given = |option: &str| args.iter().any(|i| i == option);
+        let mut options = Vec::new();
+        if let Some(interface) = self.interface.as_ref().filter(|_| !given("-I")) {
+            options.extend([String::from("-I"), interface.clone()]);
+        }
+        if let Some(cipher) = self.cipher.filter(|_| !given("-C") && !given("-I")) {
+            options.extend([String::from("-C"), cipher.to_string()]);
+        }
+        // the SDR cache of the current firmware for the commands reading the SDR, but not to dump the SDR again, nor against
+        // another BMC than the one it is of
+        let dump = args.len() >= 2 && args[0] == "sdr" && args[1] == "dump";
+        let sdr = args.iter().any(|i| ["sdr", "sensor", "sel", "fru"].contains(&i.as_str()));
+        if sdr && !dump && !given("-S") && !given("-H") {
+            if let Some(cache) = sdr::cache(self) {
+                options.extend([String::from("-S"), format!("\"{}\"", cache.display())]);
+            }
+        }
+        options.extend(args.iter().cloned());
+        options
+    }
+    fn with_args(&self, opt: &Opts) -> String {
+        self.host.with_args(&Opts {cmd: None, ipmitool_args: self.args(&opt.ipmitool_args)})
+    }
+    fn with_ipmitool_args(&self, args: &[String]) -> String {
+        self.host.with_ipmitool_args(&self.args(args))
+    }
+    /// Run ipmitool against the host and capture its output
+    fn run(&self, args: &[&str]) -> Result<String, String> {
+        use std::process::Command;
+
+        let args: Vec<String> = args.iter().map(|i| i.to_string()).collect();
+        let cmd: (&str, &str) = if cfg!(target_os = "windows") { ("cmd", "/c") } else { ("sh", "-c") };
+        match Command::new(cmd.0).arg(cmd.1).arg(self.with_ipmitool_args(&args)).output() {
+            Ok(output) if output.status.success() => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Ok(output) => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// Apply `f` to every item with at most `jobs` running at once, results are kept in order
+fn parallel<T, R, F>(items: Vec<T>, jobs: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    use std::sync::Mutex;
+
+    let n = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new((0..n).map(|_| None).collect::<Vec<Option<R>>>());
+    std::thread::scope(|s| {
+        for _ in 0..jobs.max(1).min(n) {
+            s.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((i, item)) => {
+                        let r = f(item);
+                        results.lock().unwrap()[i] = Some(r);
+                    },
+                    None => break,
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().map(|r| r.unwrap()).collect()
+}
+
 fn main() {
-    let opt = Opts::from_args();
+    let opt = Opts::from_command_line(std::env::args_os());
 
     let (connection, _) = Host::init(None);
 
     if let Some(Command::Host{cmd}) = opt.cmd {
         match cmd {
-            HostCommand::List => {
+            HostCommand::List{columns, stale: Some(stale)} => {
+                let ids = password::stale(&connection, now() - stale as i64);
+                if ids.is_empty() {
+                    println!("No hosts with the password set longer than {}s ago", stale);
+                } else {
+                    let columns = if columns.is_empty() { ["id", "ip", "user", "tags", "pswd_age"].iter().map(|c| c.to_string()).collect() } else { columns };
+                    println!();
+                    Host::table(&connection, &columns, Some(&ids));
+                    println!();
+                }
+            },
+            HostCommand::List{columns, stale: None} => {
                 println!();
-                if !Host::list(&connection) {
+                let list_some = if columns.is_empty() { Host::list(&connection) } else { Host::list_columns(&connection, &columns) };
+                if !list_some {
                     println!("Please add at least one host:");
                     println!("    ipmi.exe host add -i <ip> -u <user> -p <pswd>");
                     println!("And then use it:");
@@ -256,19 +1032,206 @@ fn main() {
                     println!("    ipmi.exe host list");
                 }
             },
-            HostCommand::Add{host} => {
-                Host::add(&connection, &host);
+            HostCommand::Add{host, probe_defaults: true, delay, name, ..} => {
+                if host.ip.contains('-') || host.ip.contains('/') {
+                    println!("Please discover the BMCs of a range or network to probe the default credentials:");
+                    println!("    ipmi.exe discover <network> --probe-defaults");
+                    return;
+                }
+                if !Host::ip_ok(&host.ip) {
+                    println!("Invalid IP: {}", host.ip);
+                    return;
+                }
+                let credentials = default_credentials(&connection);
+                match Host::probe(&host.ip, &credentials, delay) {
+                    Some((host, device)) => {
+                        println!("{:<15} {:<15} logged in", host.ip, host.user);
+                        if let Some(id) = Host::add(&connection, &host) {
+                            if let Some(template) = name {
+                                Host::edit(&connection, id, Some(&host_name(&template, 1).unwrap()), None);
+                            }
+                            mc::store(&connection, id, &device);
+                        }
+                    },
+                    None => println!("{:<15} none of the {} default credentials logged in", host.ip, credentials.len()),
+                }
+            },
+            HostCommand::Add{mut host, verify, password_prompt, name, profile, ..} => {
+                if password_prompt {
+                    match rpassword::prompt_password(format!("Password of {}: ", host.user)) {
+                        Ok(pswd) => host.pswd = pswd,
+                        Err(e) => {
+                            println!("Failed to read the password: {}", e);
+                            return;
+                        },
+                    }
+                }
+                let profile = match profile.map(|name| profile::get(&connection, &name)) {
+                    // the user and password not given are inherited
+                    Some(Some(profile)) => {
+                        for field in [&mut host.user, &mut host.pswd] {
+                            if field == "UNKNOWN" {
+                                field.clear();
+                            }
+                        }
+                        Some(profile)
+                    },
+                    Some(None) => {
+                        println!("Please list and find available profiles:");
+                        println!("    ipmi.exe host profile list");
+                        return;
+                    },
+                    None => None,
+                };
+                if host.user == "UNKNOWN" || host.pswd == "UNKNOWN" || (host.pswd.is_empty() && profile.is_none()) {
+                    println!("Please give the user and password of the host, or its profile, or probe the default ones:");
+                    println!("    ipmi.exe host add -i <ip> -u <user> -p <pswd>");
+                    println!("    ipmi.exe host add -i <ip> -u <user> --password-prompt");
+                    println!("    ipmi.exe host add -i <ip> --profile <profile>");
+                    println!("    ipmi.exe host add -i <ip> --probe-defaults");
+                    return;
+                }
+                let range = host.ip.contains('-') || host.ip.contains('/');
+                let ips = if range {
+                    match discover::addresses(&host.ip) {
+                        Ok(ips) => ips.iter().map(|ip| ip.to_string()).collect(),
+                        Err(e) => {
+                            println!("{}", e);
+                            return;
+                        },
+                    }
+                } else {
+                    vec![host.ip.clone()]
+                };
+                let hosts: Vec<(Host, Option<String>)> = ips.into_iter().enumerate().map(|(i, ip)| {
+                    let name = name.as_ref().map(|template| host_name(template, i + 1).unwrap());
+                    (Host {ip, ..host.clone()}, name)
+                }).collect();
+
+                let hosts: Vec<(Host, Option<String>, Option<mc::DeviceId>)> = if verify {
+                    parallel(hosts, 8, |(host, name)| {
+                        // log in as the host will, with the credentials, interface and cipher of its profile
+                        let login = match &profile { Some(profile) => profile.inherit(&host), None => Ok(Login::from(host.clone())) };
+                        let user = login.as_ref().map_or(host.user.clone(), |login| login.user.clone());
+                        match login.and_then(|login| mc::device_id(&login)) {
+                            Ok(device) => {
+                                println!("{:<15} {:<15} firmware {}, IPMI {}, manufacturer ID {}", host.ip, user, device.firmware, device.ipmi_version, device.manufacturer_id);
+                                Some((host, name, Some(device)))
+                            },
+                            Err(e) => {
+                                println!("{:<15} {:<15} not added, failed to log in: {}", host.ip, user, e);
+                                None
+                            },
+                        }
+                    }).into_iter().flatten().collect()
+                } else {
+                    hosts.into_iter().map(|(host, name)| (host, name, None)).collect()
+                };
+                let added = Host::add_all(&connection, &hosts, profile.as_ref().map(|profile| profile.name.as_str()));
+                if range {
+                    for ((host, name, _), (id, created)) in hosts.iter().zip(&added) {
+                        let line = format!("{:>04} {:<15} {:<7} {}", id, host.ip, if *created { "created" } else { "updated" }, name.as_deref().unwrap_or_default());
+                        println!("{}", line.trim_end());
+                    }
+                    let created = added.iter().filter(|(_, created)| *created).count();
+                    println!("{} host(s) created, {} updated", created, added.len() - created);
+                }
+            },
+            HostCommand::Check{opts} => {
+                check::run(&connection, opts);
+            },
+            HostCommand::Profile{cmd} => {
+                profile::run(&connection, cmd);
+            },
+            HostCommand::Defaults{credentials} => {
+                if !credentials.is_empty() {
+                    let value: Vec<String> = credentials.iter().map(|(user, pswd)| format!("{}:{}", user, pswd)).collect();
+                    set_setting(&connection, "probe.credentials", &value.join(","));
+                }
+                for (user, pswd) in default_credentials(&connection) {
+                    println!("{:<16} {}", user, pswd);
+                }
             },
             HostCommand::Del{id} => {
                 Host::del(&connection, id);
-            }
+            },
+            HostCommand::Edit{id, name, notes} => {
+                if !Host::edit(&connection, id, name.as_deref(), notes.as_deref()) {
+                    println!("Please list and find available <index of host>:");
+                    println!("    ipmi.exe host list");
+                }
+            },
+            HostCommand::Find{term} => {
+                let ids = Host::find(&connection, &term);
+                if ids.is_empty() {
+                    println!("No hosts found for {}", term);
+                } else {
+                    let columns: Vec<String> = ["id", "ip", "name", "mac", "product", "serial"].iter().map(|c| c.to_string()).collect();
+                    println!();
+                    Host::table(&connection, &columns, Some(&ids));
+                    println!();
+                }
+            },
+            HostCommand::Rotate{opts} => {
+                rotate::run(&connection, opts);
+            },
+            HostCommand::Policy{length, min_length, symbols, history} => {
+                let current = password::Policy::load(&connection);
+                let policy = password::Policy {
+                    length: length.unwrap_or(current.length),
+                    min_length: min_length.unwrap_or(current.min_length),
+                    symbols: symbols.unwrap_or(current.symbols),
+                    history: history.unwrap_or(current.history),
+                };
+                if let Err(e) = policy.validate() {
+                    println!("Invalid password policy: {}", e);
+                    return;
+                }
+                set_setting(&connection, "password.length", &policy.length.to_string());
+                set_setting(&connection, "password.min_length", &policy.min_length.to_string());
+                set_setting(&connection, "password.symbols", &policy.symbols);
+                set_setting(&connection, "password.history", &policy.history.to_string());
+                println!("length:     {}", policy.length);
+                println!("min length: {}", policy.min_length);
+                println!("symbols:    {}", policy.symbols);
+                println!("history:    {}", if policy.history == 0 { String::from("all") } else { policy.history.to_string() });
+            },
+            HostCommand::Tag{id, tags, remove} => {
+                if !Host::tag(&connection, id, &tags, remove) {
+                    println!("Please list and find available <index of host>:");
+                    println!("    ipmi.exe host list");
+                }
+            },
         }
+    } else if let Some(Command::Power{cmd}) = opt.cmd {
+        power::run(&connection, cmd);
+    } else if let Some(Command::Sensors{cmd, opts}) = opt.cmd {
+        sensor::run(&connection, cmd, opts);
+    } else if let Some(Command::Sel{cmd}) = opt.cmd {
+        sel::run(&connection, cmd);
+    } else if let Some(Command::Fru{cmd}) = opt.cmd {
+        fru::run(&connection, cmd);
+    } else if let Some(Command::Inventory{cmd}) = opt.cmd {
+        inventory::run(&connection, cmd);
+    } else if let Some(Command::Lan{cmd}) = opt.cmd {
+        lan::run(&connection, cmd);
+    } else if let Some(Command::Sdr{cmd}) = opt.cmd {
+        sdr::run(&connection, cmd);
+    } else if let Some(Command::BmcUser{cmd}) = opt.cmd {
+        bmc_user::run(&connection, cmd);
+    } else if let Some(Command::Audit{opts}) = opt.cmd {
+        audit::run(&connection, opts);
+    } else if let Some(Command::Discover{opts}) = opt.cmd {
+        discover::run(&connection, opts);
     } else {
         if let Some(host) = Host::get(&connection) {
             use std::process::Command;
 
-            let cmd: (&str, &str) = if cfg!(target_os = "windows") { ("cmd", "/c") } else { ("sh", "-c") };
-            Command::new(cmd.0).arg(cmd.1).arg(host.with_args(&opt)).status().unwrap();
+            // nothing to run without ipmitool arguments
+            if !opt.ipmitool_args.is_empty() {
+                let cmd: (&str, &str) = if cfg!(target_os = "windows") { ("cmd", "/c") } else { ("sh", "-c") };
+                Command::new(cmd.0).arg(cmd.1).arg(host.with_args(&opt)).status().unwrap();
+            }
         } else {
             println!("Please set default host with command:");
             println!("    ipmi.exe host use <index of host>");
@@ -313,6 +1276,129 @@ fn host_list() {
     fs::remove_file(&database).unwrap();
 }
 
+#[test]
+fn host_list_columns() {
+    let db_name = "columns.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database.clone()
+    };
+    fs::remove_file(&database).unwrap_or(());
+
+    { // case: inventory columns of hosts with or without inventory
+        let (connection, _) = Host::init(Some(db_name));
+        let columns = vec![String::from("ip"), String::from("product"), String::from("serial")];
+        assert!(!Host::list_columns(&connection, &columns));
+
+        connection.execute("INSERT INTO hosts VALUES (1, 1, '000.000.000.000', 'admin', 'admin')").unwrap();
+        connection.execute("INSERT INTO hosts VALUES (2, 0, '255.255.255.255', 'admin', 'admin')").unwrap();
+        let devices = fru::parse(&fs::read_to_string("tests/fixtures/fru_print_dell.txt").unwrap());
+        fru::store(&connection, 1, &devices, 1000);
+        assert!(Host::list_columns(&connection, &columns));
+    }
+    fs::remove_file(&database).unwrap();
+}
+
+#[test]
+fn host_find() {
+    let db_name = "find.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database.clone()
+    };
+    fs::remove_file(&database).unwrap_or(());
+
+    { // case: find by ip, name, notes, MAC and inventory
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '10.245.38.1', 'admin', 'admin')").unwrap();
+        connection.execute("INSERT INTO hosts VALUES (2, 0, '10.245.38.2', 'admin', 'admin')").unwrap();
+        assert!(Host::edit(&connection, 1, Some("r12n01"), Some("replaced PS1 in ticket 4711")));
+        assert!(Host::edit(&connection, 2, Some("r12n02"), None));
+        assert!(!Host::edit(&connection, 3, Some("r12n03"), None));
+        fru::store_details(&connection, 2, Some("ac:1f:6b:01:02:03"), Some("1.71"));
+        fru::store(&connection, 2, &fru::parse(&fs::read_to_string("tests/fixtures/fru_print_dell.txt").unwrap()), 1000);
+
+        assert_eq!(Host::find(&connection, "7XKJ123"), vec![2]);
+        assert_eq!(Host::find(&connection, "CNLOD0089L0456"), vec![2]);
+        assert_eq!(Host::find(&connection, "AC:1F:6B:01:02:03"), vec![2]);
+        assert_eq!(Host::find(&connection, "r12n01"), vec![1]);
+        assert_eq!(Host::find(&connection, "4711"), vec![1]);
+        assert_eq!(Host::find(&connection, "10.245.38.2"), vec![2]);
+        assert_eq!(Host::find(&connection, "poweredge"), vec![2]);
+        assert!(Host::find(&connection, "\"unknown").is_empty());
+    }
+    fs::remove_file(&database).unwrap();
+}
+
+#[test]
+fn host_credentials() {
+    assert_eq!(credential("root:calvin"), Ok((String::from("root"), String::from("calvin"))));
+    assert_eq!(credential("admin:pa:ss"), Ok((String::from("admin"), String::from("pa:ss"))));
+    assert!(credential("admin").is_err());
+    assert!(credential(":admin").is_err());
+
+    let db_name = "credentials.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database
+    };
+    fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = Host::init(Some(db_name));
+        assert_eq!(default_credentials(&connection)[0], (String::from("ADMIN"), String::from("ADMIN")));
+        set_setting(&connection, "probe.credentials", "root:calvin,USERID:PASSW0RD");
+        assert_eq!(default_credentials(&connection), vec![
+            (String::from("root"), String::from("calvin")), (String::from("USERID"), String::from("PASSW0RD")),
+        ]);
+    }
+    fs::remove_file(&database).unwrap();
+}
+
+#[test]
+fn host_add_range() {
+    assert_eq!(host_name("r12n{n:02}", 3), Ok(String::from("r12n03")));
+    assert_eq!(host_name("r12n{n}-bmc", 12), Ok(String::from("r12n12-bmc")));
+    assert_eq!(host_name("node", 2), Ok(String::from("node")));
+    assert!(name_template("r12n{m}").is_err());
+    assert!(name_template("r12n{n:02").is_err());
+
+    let db_name = "add_range.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database
+    };
+    fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '10.245.38.2', 'admin', 'old')").unwrap();
+        let hosts: Vec<(Host, Option<String>, Option<mc::DeviceId>)> = (1..=3).map(|n| {
+            let host = Host {ip: format!("10.245.38.{}", n), user: String::from("admin"), pswd: String::from("admin")};
+            (host, Some(host_name("r12n{n:02}", n).unwrap()), None)
+        }).collect();
+        assert_eq!(Host::add_all(&connection, &hosts, None), vec![(2, true), (1, false), (3, true)]);
+        assert_eq!(Host::by_id(&connection, 1).unwrap().pswd, "admin");
+
+        // case: a password with a quote, as given to --password-prompt
+        let host = Host {ip: String::from("10.245.38.3"), user: String::from("admin"), pswd: String::from("it's-Rack12")};
+        assert_eq!(Host::add_all(&connection, &[(host, None, None)], None), vec![(3, false)]);
+        assert_eq!(Host::by_id(&connection, 3).unwrap().pswd, "it's-Rack12");
+
+        let mut statement = connection.prepare("SELECT host_id, name FROM details ORDER BY host_id").unwrap();
+        let mut names = Vec::new();
+        while let sqlite::State::Row = statement.next().unwrap() {
+            names.push((statement.read::<i64>(0).unwrap(), statement.read::<String>(1).unwrap()));
+        }
+        assert_eq!(names, vec![(1, String::from("r12n02")), (2, String::from("r12n01")), (3, String::from("r12n03"))]);
+    }
+    fs::remove_file(&database).unwrap();
+}
+
 #[test]
 fn host_add() {
     let db_name = "add.db";
@@ -622,3 +1708,123 @@ fn host_with_args() {
     let opts = Opts { cmd: None, ipmitool_args: vec![String::from("-b"), String::from("0xff")] };
     assert_eq!(host.with_args(&opts), "ipmitool -I lanplus -H 000.000.000.000 -U admin -P admin -b 0xff");
 }
+
+#[cfg(unix)]
+#[test]
+fn opts_ipmitool_args() {
+    let opts = |args: &[&str]| Opts::from_command_line(args.iter().map(std::ffi::OsString::from));
+    // case: ipmitool commands named as or close to the subcommands
+    for args in [&["sel", "list"][..], &["-I", "lanplus", "power", "status"], &["lan", "set", "1", "ipsrc", "static"]] {
+        let opt = opts(&[&["ipmi", "--"][..], args].concat());
+        assert!(opt.cmd.is_none());
+        assert_eq!(opt.ipmitool_args, args);
+    }
+    assert!(matches!(opts(&["ipmi", "sel", "list"]).cmd, Some(Command::Sel{..})));
+    assert!(opts(&["ipmi"]).ipmitool_args.is_empty());
+}
+
+#[test]
+fn host_with_quoted_password() {
+    let host = Host { ip: String::from("10.245.38.1"), user: String::from("admin"), pswd: String::from("#Rack12-'admin'") };
+    let command = host.with_ipmitool_args(&[String::from("mc"), String::from("info")]);
+    assert_eq!(command, r#"ipmitool -I lanplus -H 10.245.38.1 -U admin -P '#Rack12-'\''admin'\''' mc info"#);
+
+    // case: the shell gives the password to ipmitool as it is, rather than the rest of the line as a comment
+    let output = std::process::Command::new("sh").arg("-c").arg(command.replacen("ipmitool", r"printf '%s\n'", 1)).output().unwrap();
+    let args: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect();
+    assert_eq!(args[6..], ["-P", "#Rack12-'admin'", "mc", "info"]);
+}
+
+#[test]
+fn host_with_sdr_cache() {
+    let host = Login::from(Host { ip: String::from("203.0.113.36"), user: String::from("admin"), pswd: String::from("admin") });
+    let dir = datadir("sdr").join(&host.ip);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("1.71.sdr"), b"").unwrap();
+
+    let args = |args: &[&str]| host.with_ipmitool_args(&args.iter().map(|i| i.to_string()).collect::<Vec<_>>());
+    // case: a cache of the host, but no BMC to tell if it is of its current firmware
+    assert_eq!(args(&["sensor"]), "ipmitool -I lanplus -H 203.0.113.36 -U admin -P admin sensor");
+    assert_eq!(args(&["-S", "my.sdr", "sensor"]), "ipmitool -I lanplus -H 203.0.113.36 -U admin -P admin -S my.sdr sensor");
+    assert_eq!(args(&["sdr", "dump", "new.sdr"]), "ipmitool -I lanplus -H 203.0.113.36 -U admin -P admin sdr dump new.sdr");
+    assert_eq!(args(&["-H", "203.0.113.37", "sensor"]), "ipmitool -I lanplus -U admin -P admin -H 203.0.113.37 sensor");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn host_tag() {
+    let db_name = "tag.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database.clone()
+    };
+    fs::remove_file(&database).unwrap_or(());
+
+    let d0 = (1i64, 0i64, String::from("000.000.000.000"), String::from("admin"), String::from("admin"));
+    let d1 = (2i64, 0i64, String::from("255.255.255.255"), String::from("ADMIN"), String::from("ADMIN"));
+    let d2 = (3i64, 0i64, String::from("200.050.005.000"), String::from("ADMIN"), String::from("ADMIN"));
+    let vd : [(i64, i64, String, String, String);3] = [ d0.clone(), d1.clone(), d2.clone() ];
+    { // case: group by tag and follow the reorder after delete
+        let (connection, _) = Host::init(Some(db_name));
+        for i in &vd {
+            connection.execute(
+                format!("INSERT INTO hosts VALUES ({}, {}, '{}', '{}', '{}')", i.0, i.1, i.2, i.3, i.4)
+            ).unwrap();
+        }
+        assert!(Host::tag(&connection, 2, &[String::from("rack1"), String::from("storage")], false));
+        assert!(Host::tag(&connection, 3, &[String::from("rack1")], false));
+        assert!(!Host::tag(&connection, 4, &[String::from("rack1")], false));
+
+        let ips = |tag| Host::group(&connection, Some(tag)).into_iter().map(|(id, host)| (id, host.host.ip)).collect::<Vec<_>>();
+        assert_eq!(ips("rack1"), vec![(2, d1.2.clone()), (3, d2.2.clone())]);
+        assert_eq!(ips("storage"), vec![(2, d1.2.clone())]);
+
+        Host::del(&connection, 1);
+        assert_eq!(ips("rack1"), vec![(1, d1.2.clone()), (2, d2.2.clone())]);
+
+        assert!(Host::tag(&connection, 1, &[String::from("rack1")], true));
+        assert_eq!(ips("rack1"), vec![(2, d2.2.clone())]);
+        assert!(Host::group(&connection, None).is_empty());
+    }
+    fs::remove_file(&database).unwrap();
+}
+
+#[test]
+fn parallel_in_order() {
+    let results = parallel((0..32).collect(), 4, |i: u64| {
+        std::thread::sleep(std::time::Duration::from_millis(32 - i));
+        i * 2
+    });
+    assert_eq!(results, (0..32).map(|i| i * 2).collect::<Vec<u64>>());
+}
+
+#[test]
+fn glob_match() {
+    assert!(glob("CPU*", "CPU 1 Temp"));
+    assert!(glob("*temp", "Inlet Temp"));
+    assert!(glob("fan?", "FAN1"));
+    assert!(glob("*", ""));
+    assert!(!glob("fan?", "FAN10"));
+    assert!(!glob("*temp", "Temp 1"));
+    assert!(glob("p*s*1*", "PSU1 IN Current"));
+}
+
+#[test]
+fn csv_field() {
+    assert_eq!(csv("CPU Temp"), "CPU Temp");
+    assert_eq!(csv("a,b"), "\"a,b\"");
+    assert_eq!(csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+}
+
+#[test]
+fn duration_seconds() {
+    assert_eq!(seconds("90"), Ok(90));
+    assert_eq!(seconds("30m"), Ok(30 * 60));
+    assert_eq!(seconds("24h"), Ok(24 * 3600));
+    assert_eq!(seconds("7d"), Ok(7 * 86400));
+    assert_eq!(seconds("2w"), Ok(14 * 86400));
+    assert!(seconds("h").is_err());
+    assert!(seconds("7y").is_err());
+}
@@ -0,0 +1,102 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Management controller identity, see IPMI 2.0 section 20.1 Get Device ID
+
+use crate::Login;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceId {
+    pub device_id: u8,
+    pub device_revision: u8,
+    /// e.g. 1.71, the minor revision is BCD encoded
+    pub firmware: String,
+    /// e.g. 2.0
+    pub ipmi_version: String,
+    /// IANA enterprise number, e.g. 10876 for Supermicro
+    pub manufacturer_id: u32,
+    pub product_id: u16,
+}
+
+impl DeviceId {
+    /// Parse the Get Device ID response data, without the completion code
+    pub fn parse(data: &[u8]) -> Result<DeviceId, String> {
+        if data.len() < 11 {
+            return Err(format!("unexpected Get Device ID response of {} bytes", data.len()));
+        }
+        Ok(DeviceId {
+            device_id: data[0],
+            device_revision: data[1] & 0x0f,
+            firmware: format!("{}.{:02x}", data[2] & 0x7f, data[3]),
+            ipmi_version: format!("{}.{}", data[4] & 0x0f, data[4] >> 4),
+            manufacturer_id: u32::from_le_bytes([data[6], data[7], data[8] & 0x0f, 0]),
+            product_id: u16::from_le_bytes([data[9], data[10]]),
+        })
+    }
+}
+
+/// Get Device ID of the BMC
+pub fn device_id(host: &Login) -> Result<DeviceId, String> {
+    let output = host.run(&["raw", "0x06", "0x01"])?;
+    let data = output.split_whitespace().map(|b| u8::from_str_radix(b, 16)).collect::<Result<Vec<u8>, _>>().map_err(|e| e.to_string())?;
+    DeviceId::parse(&data)
+}
+
+/// Keep the firmware, IPMI version and manufacturer ID of the BMC of the host
+pub fn store(connection: &sqlite::Connection, host_id: i64, id: &DeviceId) {
+    crate::fru::store_details(connection, host_id, None, Some(&id.firmware));
+    let mut statement = connection.prepare("UPDATE details SET manufacturer_id=?, ipmi_version=? WHERE host_id=?").unwrap();
+    statement.bind(1, id.manufacturer_id as i64).unwrap();
+    statement.bind(2, id.ipmi_version.as_str()).unwrap();
+    statement.bind(3, host_id).unwrap();
+    statement.next().unwrap();
+}
+
+#[test]
+fn mc_device_id() {
+    // Supermicro X11, ipmitool raw 0x06 0x01
+    let data = [0x20, 0x01, 0x01, 0x71, 0x02, 0xbf, 0x7c, 0x2a, 0x00, 0x17, 0x09, 0x00, 0x00, 0x00, 0x00];
+    let id = DeviceId::parse(&data).unwrap();
+    assert_eq!((id.device_id, id.device_revision), (0x20, 1));
+    assert_eq!((id.firmware.as_str(), id.ipmi_version.as_str()), ("1.71", "2.0"));
+    assert_eq!((id.manufacturer_id, id.product_id), (10876, 0x0917));
+
+    assert!(DeviceId::parse(&data[..6]).is_err());
+}
+
+#[test]
+fn mc_store() {
+    let db_name = "mc.db";
+
+    let database = {
+        let (_, database) = crate::Host::init(Some(db_name));
+        database
+    };
+    std::fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = crate::Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '10.245.38.1', 'admin', 'admin')").unwrap();
+        crate::fru::store_details(&connection, 1, Some("ac:1f:6b:01:02:03"), Some("1.70"));
+
+        let data = [0x20, 0x01, 0x01, 0x71, 0x02, 0xbf, 0x7c, 0x2a, 0x00, 0x17, 0x09];
+        store(&connection, 1, &DeviceId::parse(&data).unwrap());
+        let mut statement = connection.prepare("SELECT mac, firmware, manufacturer_id, ipmi_version FROM details WHERE host_id=1").unwrap();
+        assert_eq!(statement.next().unwrap(), sqlite::State::Row);
+        assert_eq!(statement.read::<String>(0).unwrap(), "ac:1f:6b:01:02:03");
+        assert_eq!(statement.read::<String>(1).unwrap(), "1.71");
+        assert_eq!(statement.read::<i64>(2).unwrap(), 10876);
+        assert_eq!(statement.read::<String>(3).unwrap(), "2.0");
+        let columns: Vec<String> = ["id", "firmware", "ipmi_version", "manufacturer_id"].iter().map(|c| c.to_string()).collect();
+        assert!(crate::Host::table(&connection, &columns, None));
+    }
+    std::fs::remove_file(&database).unwrap();
+}
@@ -0,0 +1,200 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Password policy of the BMC users of the hosts, and the salted hashes of the passwords the hosts had
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+/// The max password length of IPMI 2.0, in bytes
+pub const MAX_LENGTH: usize = 20;
+
+const LOWER: &str = "abcdefghijkmnopqrstuvwxyz";
+const UPPER: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ";
+const DIGITS: &str = "23456789";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    /// The length of the generated passwords
+    pub length: usize,
+    /// The min length of the given passwords
+    pub min_length: usize,
+    /// The symbols in the generated passwords, some BMCs take none
+    pub symbols: String,
+    /// The number of the last passwords of a host not to reuse, 0 for all of them
+    pub history: usize,
+}
+
+impl Policy {
+    pub fn load(connection: &sqlite::Connection) -> Policy {
+        let get = |key, default| crate::setting(connection, key).and_then(|v| v.parse::<usize>().ok()).unwrap_or(default);
+        Policy {
+            length: get("password.length", MAX_LENGTH),
+            min_length: get("password.min_length", 12),
+            // the symbols a policy kept before that are not safe unquoted are left out
+            symbols: crate::setting(connection, "password.symbols").map(|s| s.chars().filter(|c| safe(*c)).collect())
+                .unwrap_or_else(|| String::from("+-=@_")),
+            history: get("password.history", 0),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_length == 0 || self.min_length > self.length || self.length > MAX_LENGTH {
+            return Err(format!("the lengths must be 1 <= min length <= length <= {}", MAX_LENGTH));
+        }
+        if let Some(c) = self.symbols.chars().find(|c| !safe(*c)) {
+            return Err(format!("invalid symbol {:?}, expect some of {}", c, crate::SHELL_SAFE));
+        }
+        Ok(())
+    }
+
+    /// A random password of the policy, with a lower and upper case letter, a digit and a symbol if any
+    pub fn generate(&self) -> String {
+        let mut rng = rand::thread_rng();
+        let classes: Vec<&str> = [LOWER, UPPER, DIGITS, self.symbols.as_str()].iter().copied().filter(|c| !c.is_empty()).collect();
+        let all: Vec<char> = classes.concat().chars().collect();
+        let mut password: Vec<char> = classes.iter().map(|c| c.chars().collect::<Vec<_>>()).map(|c| c[rng.gen_range(0..c.len())]).collect();
+        while password.len() < self.length {
+            password.push(all[rng.gen_range(0..all.len())]);
+        }
+        password.truncate(self.length);
+        password.shuffle(&mut rng);
+        password.into_iter().collect()
+    }
+
+    /// Check a given password against the policy and the BMC limits
+    pub fn check(&self, password: &str) -> Result<(), String> {
+        if password.len() < self.min_length || password.len() > MAX_LENGTH {
+            return Err(format!("the password must be {} to {} bytes", self.min_length, MAX_LENGTH));
+        }
+        if !password.chars().all(|c| c.is_ascii_alphanumeric() || symbol(c)) {
+            return Err(String::from("the password must be printable ASCII other than space, \", \\, $, ` and %"));
+        }
+        let classes = [char::is_ascii_lowercase, char::is_ascii_uppercase, char::is_ascii_digit];
+        if !classes.iter().all(|class| password.chars().any(|c| class(&c))) {
+            return Err(String::from("the password must have a lower and upper case letter and a digit"));
+        }
+        Ok(())
+    }
+}
+
+/// The symbols safe to give to ipmitool through the shell quoted
+fn symbol(c: char) -> bool {
+    c.is_ascii_punctuation() && !crate::bmc_user::UNQUOTABLE.contains(&c)
+}
+
+/// The symbols of the generated passwords, safe to give to ipmitool through the shell even unquoted
+fn safe(c: char) -> bool {
+    crate::SHELL_SAFE.contains(c)
+}
+
+fn hash(salt: &str, password: &str) -> String {
+    Sha256::digest(format!("{}{}", salt, password)).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// If the host had the password, one of the last `history` ones or any if 0
+pub fn used(connection: &sqlite::Connection, host_id: i64, password: &str, history: usize) -> bool {
+    let mut statement = connection.prepare("SELECT salt, hash FROM passwords WHERE host_id=? ORDER BY time DESC, ROWID DESC").unwrap();
+    statement.bind(1, host_id).unwrap();
+    let mut n = 0;
+    while let sqlite::State::Row = statement.next().unwrap() {
+        if history != 0 && n == history {
+            break;
+        }
+        n += 1;
+        if hash(&statement.read::<String>(0).unwrap(), password) == statement.read::<String>(1).unwrap() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Keep the salted hash of the new password of the host, unless it is the last one already
+pub fn record(connection: &sqlite::Connection, host_id: i64, password: &str, time: i64) {
+    if used(connection, host_id, password, 1) {
+        return;
+    }
+    let salt: String = (0..16).map(|_| format!("{:02x}", rand::thread_rng().gen::<u8>())).collect();
+    let mut statement = connection.prepare("INSERT INTO passwords (host_id, salt, hash, time) VALUES (?, ?, ?, ?)").unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.bind(2, salt.as_str()).unwrap();
+    statement.bind(3, hash(&salt, password).as_str()).unwrap();
+    statement.bind(4, time).unwrap();
+    statement.next().unwrap();
+}
+
+/// The hosts with the password set before the time, or not known when
+pub fn stale(connection: &sqlite::Connection, before: i64) -> Vec<i64> {
+    let mut statement = connection.prepare(
+        "SELECT id FROM hosts WHERE IFNULL((SELECT MAX(time) FROM passwords WHERE host_id=hosts.id), 0) < ? ORDER BY ROWID ASC"
+    ).unwrap();
+    statement.bind(1, before).unwrap();
+    let mut ids = Vec::new();
+    while let sqlite::State::Row = statement.next().unwrap() {
+        ids.push(statement.read::<i64>(0).unwrap());
+    }
+    ids
+}
+
+#[test]
+fn password_policy() {
+    let policy = Policy {length: 20, min_length: 12, symbols: String::from("+-=@_"), history: 0};
+    assert!(policy.validate().is_ok());
+    for _ in 0..100 {
+        let password = policy.generate();
+        assert_eq!(password.len(), 20);
+        assert!(policy.check(&password).is_ok(), "{}", password);
+        assert!(password.chars().any(|c| policy.symbols.contains(c)), "{}", password);
+    }
+    let short = Policy {length: 4, min_length: 4, symbols: String::new(), history: 0};
+    assert!(short.generate().chars().all(|c| c.is_ascii_alphanumeric()));
+
+    assert!(Policy {length: 21, ..policy.clone()}.validate().is_err());
+    assert!(Policy {symbols: String::from("\""), ..policy.clone()}.validate().is_err());
+    assert!(Policy {symbols: String::from("#"), ..policy.clone()}.validate().is_err());
+    assert!(Policy {symbols: String::from("';&|()<>"), ..policy.clone()}.validate().is_err());
+    assert!(policy.check("Short1").is_err());
+    assert!(policy.check("nocapitals1234").is_err());
+    assert!(policy.check("With space 1234").is_err());
+    assert!(policy.check("With-$HOME-1234").is_err());
+    assert!(policy.check("Long-enough-1234").is_ok());
+    assert!(policy.check("Twenty-one-bytes-1234").is_err());
+}
+
+#[test]
+fn password_history() {
+    let db_name = "password.db";
+
+    let database = {
+        let (_, database) = crate::Host::init(Some(db_name));
+        database
+    };
+    std::fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = crate::Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '10.245.38.1', 'admin', 'admin')").unwrap();
+        connection.execute("INSERT INTO hosts VALUES (2, 0, '10.245.38.2', 'admin', 'admin')").unwrap();
+
+        record(&connection, 1, "First-password-1", 1000);
+        record(&connection, 1, "First-password-1", 1500);
+        record(&connection, 1, "Second-password-2", 2000);
+        assert!(used(&connection, 1, "First-password-1", 0));
+        assert!(!used(&connection, 1, "First-password-1", 1));
+        assert!(used(&connection, 1, "Second-password-2", 1));
+        assert!(!used(&connection, 2, "First-password-1", 0));
+
+        assert_eq!(stale(&connection, 1500), vec![2]);
+        assert_eq!(stale(&connection, 2500), vec![1, 2]);
+    }
+    std::fs::remove_file(&database).unwrap();
+}
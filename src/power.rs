@@ -0,0 +1,378 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+use std::fs;
+use std::path;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use crate::{Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub enum PowerCommand {
+    /// Soft off (ACPI) the host(s), then hard off if still on after the grace period
+    Shutdown {
+        /// Seconds to wait for the soft off before escalating to hard off
+        #[structopt(short, long, default_value = "300")]
+        grace: u64,
+        /// Seconds between chassis power status polls
+        #[structopt(short, long, default_value = "10")]
+        poll: u64,
+        #[structopt(flatten)]
+        group: Group,
+    },
+    /// Power the host groups of a plan up or down, stage by stage
+    Sequence {
+        /// Power the stages up in order, or down in reverse order
+        #[structopt(possible_values = &["up", "down"])]
+        direction: String,
+        /// The sequence plan (TOML, or YAML by the .yaml or .yml extension)
+        plan: path::PathBuf,
+        /// Print the stages and their actions without running them
+        #[structopt(short = "n", long)]
+        dry_run: bool,
+        /// Start from the stage that failed in the last run of the plan
+        #[structopt(short, long)]
+        resume: bool,
+        /// Max number of hosts to work on in parallel
+        #[structopt(short, long, default_value = "8")]
+        jobs: usize,
+    },
+}
+
+/// A power sequence plan, e.g.
+///
+/// ```toml
+/// [[stage]]
+/// tag   = "storage"
+/// wait  = true
+/// delay = 60
+///
+/// [[stage]]
+/// tag   = "compute"
+/// ```
+///
+/// or the same in YAML:
+///
+/// ```yaml
+/// stage:
+///   - tag: storage
+///     wait: true
+///     delay: 60
+///   - tag: compute
+/// ```
+#[derive(Deserialize, Debug)]
+struct Plan {
+    #[serde(rename = "stage")]
+    stages: Vec<Stage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Stage {
+    /// The tag of the hosts in the stage
+    tag: String,
+    /// Seconds to wait after the stage before the next one
+    #[serde(default)]
+    delay: u64,
+    /// Wait until every host of the stage is powered on (up) or off (down)
+    #[serde(default)]
+    wait: bool,
+    /// Seconds to wait for the power state, also the soft off grace period when going down
+    #[serde(default = "Stage::timeout")]
+    timeout: u64,
+}
+
+impl Stage {
+    fn timeout() -> u64 {
+        300
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Shutdown {
+    AlreadyOff,
+    SoftOff(u64),
+    HardOff,
+    Failed(String),
+}
+
+/// Parse `chassis power status` output, `Some(true)` if the chassis is on
+fn power_is_on(output: &str) -> Option<bool> {
+    let status = output.trim().strip_prefix("Chassis Power is ")?;
+    match status.trim() {
+        "on"  => Some(true),
+        "off" => Some(false),
+        _     => None,
+    }
+}
+
+fn status(host: &Login) -> Result<bool, String> {
+    let output = host.run(&["chassis", "power", "status"])?;
+    power_is_on(&output).ok_or(format!("unexpected power status: {}", output.trim()))
+}
+
+/// Poll the power status until the chassis is `on` (or off) or `timeout` is reached
+fn wait_for(host: &Login, on: bool, timeout: Duration, poll: Duration) -> Result<bool, String> {
+    let start = Instant::now();
+    loop {
+        if status(host)? == on {
+            return Ok(true);
+        }
+        if start.elapsed() >= timeout {
+            return Ok(false);
+        }
+        thread::sleep(poll.min(timeout.saturating_sub(start.elapsed())));
+    }
+}
+
+fn shutdown(host: &Login, grace: u64, poll: u64, log: &dyn Fn(&str)) -> Shutdown {
+    let poll = Duration::from_secs(poll.max(1));
+
+    match status(host) {
+        Ok(false) => {
+            log("chassis power is already off");
+            return Shutdown::AlreadyOff;
+        },
+        Ok(true) => {},
+        Err(e) => return Shutdown::Failed(e),
+    }
+
+    log("chassis power soft");
+    if let Err(e) = host.run(&["chassis", "power", "soft"]) {
+        return Shutdown::Failed(e);
+    }
+    let start = Instant::now();
+    match wait_for(host, false, Duration::from_secs(grace), poll) {
+        Ok(true) => {
+            log(&format!("chassis power is off after {}s", start.elapsed().as_secs()));
+            return Shutdown::SoftOff(start.elapsed().as_secs());
+        },
+        Ok(false) => {},
+        Err(e) => return Shutdown::Failed(e),
+    }
+
+    log(&format!("chassis power is still on after {}s, chassis power off", grace));
+    if let Err(e) = host.run(&["chassis", "power", "off"]) {
+        return Shutdown::Failed(e);
+    }
+    match wait_for(host, false, poll * 3, poll) {
+        Ok(true) => {
+            log("chassis power is off");
+            Shutdown::HardOff
+        },
+        Ok(false) => Shutdown::Failed(String::from("chassis power is still on after hard off")),
+        Err(e) => Shutdown::Failed(e),
+    }
+}
+
+fn power_on(host: &Login, wait: Option<u64>, poll: u64, log: &dyn Fn(&str)) -> Result<(), String> {
+    if status(host)? {
+        log("chassis power is already on");
+        return Ok(());
+    }
+
+    log("chassis power on");
+    host.run(&["chassis", "power", "on"])?;
+    if let Some(timeout) = wait {
+        let start = Instant::now();
+        if !wait_for(host, true, Duration::from_secs(timeout), Duration::from_secs(poll))? {
+            return Err(format!("chassis power is still off after {}s", timeout));
+        }
+        log(&format!("chassis power is on after {}s", start.elapsed().as_secs()));
+    }
+    Ok(())
+}
+
+fn power_soft(host: &Login, log: &dyn Fn(&str)) -> Result<(), String> {
+    if !status(host)? {
+        log("chassis power is already off");
+        return Ok(());
+    }
+
+    log("chassis power soft");
+    host.run(&["chassis", "power", "soft"]).map(|_| ())
+}
+
+fn sequence(connection: &sqlite::Connection, up: bool, plan: &path::Path, dry_run: bool, resume: bool, jobs: usize) -> bool {
+    let text = match fs::read_to_string(plan) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("Unable to read {}: {}", plan.display(), e);
+            return false;
+        },
+    };
+    let parsed = if plan.extension().is_some_and(|x| x == "yaml" || x == "yml") {
+        serde_yaml::from_str::<Plan>(&text).map_err(|e| e.to_string())
+    } else {
+        toml::from_str::<Plan>(&text).map_err(|e| e.to_string())
+    };
+    let plan_stages = match parsed {
+        Ok(p) => p.stages,
+        Err(e) => {
+            println!("Invalid plan {}: {}", plan.display(), e);
+            return false;
+        },
+    };
+    let mut stages: Vec<(usize, &Stage)> = plan_stages.iter().enumerate().collect();
+    if !up {
+        stages.reverse();
+    }
+
+    // progress is keyed by the plan path and direction
+    let key = fs::canonicalize(plan).unwrap_or_else(|_| plan.to_path_buf()).display().to_string();
+    let direction = if up { "up" } else { "down" };
+    let mut from = 0;
+    if resume {
+        let mut statement = connection.prepare("SELECT stage FROM sequences WHERE plan=? AND direction=?").unwrap();
+        statement.bind(1, key.as_str()).unwrap();
+        statement.bind(2, direction).unwrap();
+        if let sqlite::State::Row = statement.next().unwrap() {
+            from = statement.read::<i64>(0).unwrap() as usize;
+        }
+    }
+
+    let start = Instant::now();
+    for (n, (i, stage)) in stages.iter().enumerate().skip(from) {
+        let hosts = Host::group(connection, Some(&stage.tag));
+        println!("stage {}/{}: {} ({} hosts)", n + 1, stages.len(), stage.tag, hosts.len());
+        // likely a typo in the plan or hosts not tagged yet, the next stages are not to go on without it
+        let empty = hosts.is_empty();
+        if empty {
+            println!("No hosts tagged {}, please tag them with command:", stage.tag);
+            println!("    ipmi.exe host tag <index of host> {}", stage.tag);
+        }
+        if dry_run {
+            for (id, host) in &hosts {
+                let action = if up { "chassis power on" } else if stage.wait { "chassis power soft, chassis power off if still on" } else { "chassis power soft" };
+                println!("    {:>04} {:<15} {}", id, host.ip, action);
+            }
+            if stage.wait {
+                println!("    wait until powered {}, up to {}s", if up { "on" } else { "off" }, stage.timeout);
+            }
+            if stage.delay > 0 && n + 1 < stages.len() {
+                println!("    delay {}s", stage.delay);
+            }
+            continue;
+        }
+
+        let results = crate::parallel(hosts, jobs, |(id, host)| {
+            let log = |msg: &str| println!("[{:>5}s] {:>04} {:<15} {}", start.elapsed().as_secs(), id, host.ip, msg);
+            let result = if up {
+                power_on(&host, if stage.wait { Some(stage.timeout) } else { None }, 10, &log)
+            } else if stage.wait {
+                match shutdown(&host, stage.timeout, 10, &log) {
+                    Shutdown::Failed(e) => Err(e),
+                    _ => Ok(()),
+                }
+            } else {
+                power_soft(&host, &log)
+            };
+            if let Err(e) = &result {
+                log(&format!("failed: {}", e));
+            }
+            result.is_ok()
+        });
+        if empty || results.iter().any(|ok| !ok) {
+            let mut statement = connection.prepare("INSERT OR REPLACE INTO sequences(plan, direction, stage) VALUES (?, ?, ?)").unwrap();
+            statement.bind(1, key.as_str()).unwrap();
+            statement.bind(2, direction).unwrap();
+            statement.bind(3, n as i64).unwrap();
+            statement.next().unwrap();
+            println!("Stage {} ({}) failed, fix it and continue with:", i + 1, stage.tag);
+            println!("    ipmi.exe power sequence {} {} --resume", direction, plan.display());
+            return false;
+        }
+        if stage.delay > 0 && n + 1 < stages.len() {
+            println!("[{:>5}s] delay {}s", start.elapsed().as_secs(), stage.delay);
+            thread::sleep(Duration::from_secs(stage.delay));
+        }
+    }
+
+    if !dry_run {
+        let mut statement = connection.prepare("DELETE FROM sequences WHERE plan=? AND direction=?").unwrap();
+        statement.bind(1, key.as_str()).unwrap();
+        statement.bind(2, direction).unwrap();
+        statement.next().unwrap();
+    }
+    true
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: PowerCommand) {
+    match cmd {
+        PowerCommand::Shutdown{grace, poll, group} => {
+            let hosts = Host::group(connection, group.tag.as_deref());
+            if hosts.is_empty() {
+                println!("Please set default host or tag hosts with command:");
+                println!("    ipmi.exe host use <index of host>");
+                println!("    ipmi.exe host tag <index of host> <tag>");
+                return;
+            }
+
+            let start = Instant::now();
+            let results = crate::parallel(hosts, group.jobs, |(id, host)| {
+                let log = |msg: &str| println!("[{:>5}s] {:>04} {:<15} {}", start.elapsed().as_secs(), id, host.ip, msg);
+                let result = shutdown(&host, grace, poll, &log);
+                if let Shutdown::Failed(e) = &result {
+                    log(&format!("failed: {}", e));
+                }
+                (id, host, result)
+            });
+
+            println!();
+            for (id, host, result) in &results {
+                let result = match result {
+                    Shutdown::AlreadyOff => String::from("already off"),
+                    Shutdown::SoftOff(s) => format!("soft off in {}s", s),
+                    Shutdown::HardOff    => String::from("hard off"),
+                    Shutdown::Failed(e)  => format!("failed: {}", e),
+                };
+                println!("{:>04} {:<15} {}", id, host.ip, result);
+            }
+        },
+        PowerCommand::Sequence{direction, plan, dry_run, resume, jobs} => {
+            sequence(connection, direction == "up", &plan, dry_run, resume, jobs);
+        },
+    }
+}
+
+#[test]
+fn power_status() {
+    assert_eq!(power_is_on("Chassis Power is on\n"), Some(true));
+    assert_eq!(power_is_on("Chassis Power is off\n"), Some(false));
+    assert_eq!(power_is_on("Error: Unable to establish IPMI v2 / RMCP+ session\n"), None);
+}
+
+#[test]
+fn power_plan() {
+    let plan: Plan = toml::from_str(
+        "
+        [[stage]]
+        tag   = 'storage'
+        wait  = true
+        delay = 60
+
+        [[stage]]
+        tag     = 'compute'
+        timeout = 900
+        "
+    ).unwrap();
+    assert_eq!(plan.stages.len(), 2);
+    assert_eq!((plan.stages[0].tag.as_str(), plan.stages[0].wait, plan.stages[0].delay, plan.stages[0].timeout), ("storage", true, 60, 300));
+    assert_eq!((plan.stages[1].tag.as_str(), plan.stages[1].wait, plan.stages[1].delay, plan.stages[1].timeout), ("compute", false, 0, 900));
+
+    assert!(toml::from_str::<Plan>("[[stage]]\ndelay = 60\n").is_err());
+
+    let yaml: Plan = serde_yaml::from_str("stage:\n  - tag: storage\n    wait: true\n    delay: 60\n  - tag: compute\n    timeout: 900\n").unwrap();
+    assert_eq!(yaml.stages.iter().map(|s| (s.tag.as_str(), s.wait, s.delay, s.timeout)).collect::<Vec<_>>(),
+        plan.stages.iter().map(|s| (s.tag.as_str(), s.wait, s.delay, s.timeout)).collect::<Vec<_>>());
+}
@@ -0,0 +1,292 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Named credential profiles the hosts inherit the user, password, interface and cipher suite of, unless the host has its
+//! own user or password
+
+use structopt::StructOpt;
+
+use crate::{Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub enum ProfileCommand {
+    /// List the profiles with the number of hosts of each
+    List,
+    /// Add a profile, or change the given settings of one
+    Set {
+        name: String,
+        /// The user name
+        #[structopt(short, long)]
+        user: Option<String>,
+        /// The password, or a reference to it: env:<variable> or file:<path>, e.g. env:LAB_PSWD
+        #[structopt(short, long)]
+        pswd: Option<String>,
+        /// The ipmitool interface, e.g. lan
+        #[structopt(long)]
+        interface: Option<String>,
+        /// The cipher suite ID of lanplus, -1 for the ipmitool default
+        #[structopt(short, long, allow_hyphen_values = true)]
+        cipher: Option<i64>,
+    },
+    /// Delete a profile no host uses
+    Del {
+        name: String,
+    },
+    /// Make hosts use a profile
+    Attach {
+        name: String,
+        ids: Vec<i64>,
+        /// Clear the own user and password of the hosts, to inherit the ones of the profile
+        #[structopt(long)]
+        inherit: bool,
+    },
+    /// Make hosts use no profile, with the credentials of the profile they inherit as their own
+    Detach {
+        ids: Vec<i64>,
+    },
+}
+
+/// The user of a host as listed, its own or the one of its profile
+pub const USER: &str = "COALESCE(NULLIF(user, ''), (SELECT profiles.user FROM host_profiles JOIN profiles ON profiles.name=host_profiles.profile WHERE host_id=hosts.id), '')";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub user: String,
+    /// The password or a reference to it, see `password`
+    pub pswd: String,
+    pub interface: String,
+    pub cipher: Option<u8>,
+}
+
+impl Profile {
+    /// The host as it logs in with the profile, with the user and password of the profile unless it has its own
+    pub fn inherit(&self, host: &Host) -> Result<Login, String> {
+        Ok(Login {
+            host: Host {
+                ip: host.ip.clone(),
+                user: if host.user.is_empty() { self.user.clone() } else { host.user.clone() },
+                pswd: if host.pswd.is_empty() { password(&self.pswd)? } else { host.pswd.clone() },
+            },
+            interface: Some(self.interface.clone()),
+            cipher: self.cipher,
+        })
+    }
+}
+
+/// The password of a reference: env:<variable>, file:<path> of which the first line is read, or the password itself
+pub fn password(reference: &str) -> Result<String, String> {
+    if let Some(variable) = reference.strip_prefix("env:") {
+        std::env::var(variable).map_err(|_| format!("environment variable {} not set", variable))
+    } else if let Some(path) = reference.strip_prefix("file:") {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        Ok(content.lines().next().unwrap_or_default().to_string())
+    } else {
+        Ok(String::from(reference))
+    }
+}
+
+pub fn get(connection: &sqlite::Connection, name: &str) -> Option<Profile> {
+    let mut statement = connection.prepare("SELECT name, user, pswd, interface, cipher FROM profiles WHERE name=?").unwrap();
+    statement.bind(1, name).unwrap();
+    match statement.next().unwrap() {
+        sqlite::State::Row => Some(Profile {
+            name: statement.read::<String>(0).unwrap(),
+            user: statement.read::<String>(1).unwrap(),
+            pswd: statement.read::<String>(2).unwrap(),
+            interface: statement.read::<String>(3).unwrap(),
+            cipher: statement.read::<Option<i64>>(4).unwrap().map(|c| c as u8),
+        }),
+        sqlite::State::Done => None,
+    }
+}
+
+fn set(connection: &sqlite::Connection, profile: &Profile) {
+    let mut statement = connection.prepare("INSERT OR REPLACE INTO profiles (name, user, pswd, interface, cipher) VALUES (?, ?, ?, ?, ?)").unwrap();
+    statement.bind(1, profile.name.as_str()).unwrap();
+    statement.bind(2, profile.user.as_str()).unwrap();
+    statement.bind(3, profile.pswd.as_str()).unwrap();
+    statement.bind(4, profile.interface.as_str()).unwrap();
+    match profile.cipher {
+        Some(cipher) => statement.bind(5, cipher as i64).unwrap(),
+        None => statement.bind(5, ()).unwrap(),
+    }
+    statement.next().unwrap();
+}
+
+/// The hosts using the profile
+fn hosts(connection: &sqlite::Connection, name: &str) -> Vec<i64> {
+    let mut statement = connection.prepare("SELECT host_id FROM host_profiles WHERE profile=? ORDER BY host_id").unwrap();
+    statement.bind(1, name).unwrap();
+    let mut ids = Vec::new();
+    while let sqlite::State::Row = statement.next().unwrap() {
+        ids.push(statement.read::<i64>(0).unwrap());
+    }
+    ids
+}
+
+/// The profile the host inherits its password of, if any
+pub fn inherited(connection: &sqlite::Connection, host_id: i64) -> Option<String> {
+    let mut statement = connection.prepare(
+        "SELECT profile FROM host_profiles JOIN hosts ON hosts.id=host_profiles.host_id WHERE host_id=? AND hosts.pswd=''"
+    ).unwrap();
+    statement.bind(1, host_id).unwrap();
+    match statement.next().unwrap() {
+        sqlite::State::Row => Some(statement.read::<String>(0).unwrap()),
+        sqlite::State::Done => None,
+    }
+}
+
+/// Make the host use the profile, with the user and password of the profile instead of its own if `inherit`
+pub fn attach(connection: &sqlite::Connection, host_id: i64, name: &str, inherit: bool) -> Result<(), String> {
+    let mut statement = connection.prepare("INSERT OR REPLACE INTO host_profiles (host_id, profile) VALUES (?, ?)").unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.bind(2, name).unwrap();
+    statement.next().map_err(|e| e.to_string())?;
+    if inherit {
+        // the user is unique by IP, the host may have a sibling inheriting it already
+        connection.execute(format!("UPDATE hosts SET user='', pswd='' WHERE id={}", host_id)).map_err(|_| {
+            String::from("another host of the IP inherits the user of a profile already")
+        })?;
+    }
+    Ok(())
+}
+
+/// Make the host use no profile, keeping the credentials it inherits as its own
+fn detach(connection: &sqlite::Connection, host_id: i64) -> Result<(), String> {
+    let host = Host::by_id(connection, host_id).ok_or(format!("no host {}", host_id))?;
+    let mut statement = connection.prepare("UPDATE hosts SET user=?, pswd=? WHERE id=?").unwrap();
+    statement.bind(1, host.user.as_str()).unwrap();
+    statement.bind(2, host.pswd.as_str()).unwrap();
+    statement.bind(3, host_id).unwrap();
+    statement.next().map_err(|_| format!("host {} has a sibling with the user {} already", host_id, host.user))?;
+    connection.execute(format!("DELETE FROM host_profiles WHERE host_id={}", host_id)).unwrap();
+    Ok(())
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: ProfileCommand) {
+    match cmd {
+        ProfileCommand::List => {
+            let mut statement = connection.prepare(
+                "SELECT name, user, pswd, interface, IFNULL(CAST(cipher AS TEXT), ''), (SELECT COUNT(*) FROM host_profiles WHERE profile=name) FROM profiles ORDER BY name"
+            ).unwrap();
+            println!("{:<16} {:<16} {:<24} {:<10} {:<6} Hosts", "Name", "User", "Password", "Interface", "Cipher");
+            println!("{:<16} {:<16} {:<24} {:<10} {:<6} -----", "----", "----", "--------", "---------", "------");
+            while let sqlite::State::Row = statement.next().unwrap() {
+                let pswd = statement.read::<String>(2).unwrap();
+                // the password itself is not shown, its reference is
+                let pswd = if pswd.starts_with("env:") || pswd.starts_with("file:") { pswd } else { String::from("********") };
+                println!("{:<16} {:<16} {:<24} {:<10} {:<6} {}",
+                    statement.read::<String>(0).unwrap(), statement.read::<String>(1).unwrap(), pswd,
+                    statement.read::<String>(3).unwrap(), statement.read::<String>(4).unwrap(), statement.read::<i64>(5).unwrap());
+            }
+        },
+        ProfileCommand::Set{name, user, pswd, interface, cipher} => {
+            if let Some(cipher) = cipher.filter(|c| *c > 17) {
+                println!("Invalid cipher suite ID {}, expect 0 to 17, or -1 for the ipmitool default", cipher);
+                return;
+            }
+            let profile = match (get(connection, &name), user, pswd) {
+                (Some(current), user, pswd) => Profile {
+                    user: user.unwrap_or(current.user), pswd: pswd.unwrap_or(current.pswd), interface: interface.unwrap_or(current.interface),
+                    cipher: match cipher { Some(c) if c < 0 => None, Some(c) => Some(c as u8), None => current.cipher }, ..current
+                },
+                (None, Some(user), Some(pswd)) => Profile {
+                    name, user, pswd, interface: interface.unwrap_or_else(|| String::from("lanplus")), cipher: cipher.filter(|c| *c >= 0).map(|c| c as u8),
+                },
+                (None, ..) => {
+                    println!("Please give the user and password of the new profile:");
+                    println!("    ipmi.exe host profile set {} -u <user> -p <pswd>", name);
+                    return;
+                },
+            };
+            if let Err(e) = password(&profile.pswd) {
+                println!("Warning: {}", e);
+            }
+            set(connection, &profile);
+        },
+        ProfileCommand::Del{name} => {
+            let ids = hosts(connection, &name);
+            if !ids.is_empty() {
+                println!("Profile {} is used by {} host(s), please detach them first:", name, ids.len());
+                println!("    ipmi.exe host profile detach {}", ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(" "));
+                return;
+            }
+            connection.execute(format!("DELETE FROM profiles WHERE name='{}'", name.replace('\'', "''"))).unwrap();
+        },
+        ProfileCommand::Attach{name, ids, inherit} => {
+            if get(connection, &name).is_none() {
+                println!("Please list and find available profiles:");
+                println!("    ipmi.exe host profile list");
+                return;
+            }
+            for id in ids {
+                match Host::by_id(connection, id).map(|_| attach(connection, id, &name, inherit)) {
+                    Some(Ok(())) => {},
+                    Some(Err(e)) => println!("{:>04} failed: {}", id, e),
+                    None => println!("{:>04} failed: no such host", id),
+                }
+            }
+        },
+        ProfileCommand::Detach{ids} => {
+            for id in ids {
+                if let Err(e) = detach(connection, id) {
+                    println!("{:>04} failed: {}", id, e);
+                }
+            }
+        },
+    }
+}
+
+#[test]
+fn profile_resolve() {
+    std::env::set_var("PROFILE_TEST_PSWD", "from-env");
+    assert_eq!(password("env:PROFILE_TEST_PSWD"), Ok(String::from("from-env")));
+    assert!(password("env:PROFILE_TEST_UNSET").is_err());
+    assert_eq!(password("plain"), Ok(String::from("plain")));
+
+    let db_name = "profile.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database
+    };
+    std::fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 1, '10.245.38.1', 'admin', 'admin')").unwrap();
+        connection.execute("INSERT INTO hosts VALUES (2, 0, '10.245.38.2', 'root', 'own')").unwrap();
+        set(&connection, &Profile {
+            name: String::from("lab"), user: String::from("ADMIN"), pswd: String::from("env:PROFILE_TEST_PSWD"), interface: String::from("lanplus"), cipher: Some(17),
+        });
+
+        // the host inherits the user and password, the other keeps its own as overrides
+        attach(&connection, 1, "lab", true).unwrap();
+        attach(&connection, 2, "lab", false).unwrap();
+        let host = Host::get(&connection).unwrap();
+        assert_eq!((host.user.as_str(), host.pswd.as_str(), host.cipher), ("ADMIN", "from-env", Some(17)));
+        assert_eq!(host.with_ipmitool_args(&[]), "ipmitool -H 10.245.38.1 -U ADMIN -P from-env -I lanplus -C 17");
+        let host = Host::by_id(&connection, 2).unwrap();
+        assert_eq!((host.user.as_str(), host.pswd.as_str(), host.cipher), ("root", "own", Some(17)));
+        let host = get(&connection, "lab").unwrap().inherit(&Host {ip: String::from("10.245.38.3"), ..Default::default()}).unwrap();
+        assert_eq!(host.with_ipmitool_args(&[]), "ipmitool -H 10.245.38.3 -U ADMIN -P from-env -I lanplus -C 17");
+
+        assert_eq!(hosts(&connection, "lab"), vec![1, 2]);
+        assert_eq!((inherited(&connection, 1), inherited(&connection, 2)), (Some(String::from("lab")), None));
+        detach(&connection, 1).unwrap();
+        assert_eq!(inherited(&connection, 1), None);
+        let host = Host::by_id(&connection, 1).unwrap();
+        assert_eq!((host.user.as_str(), host.pswd.as_str(), host.cipher), ("ADMIN", "from-env", None));
+        assert_eq!(hosts(&connection, "lab"), vec![2]);
+    }
+    std::fs::remove_file(&database).unwrap();
+}
@@ -0,0 +1,189 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Session-less RMCP messages over UDP 623: the ASF Presence Ping (see IPMI 2.0 section 13.2.3) and
+//! Get Channel Authentication Capabilities (section 22.13), answered by a BMC before any login
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+const PORT: u16 = 623;
+/// The IANA enterprise number of the ASF messages
+const ASF_IANA: [u8; 4] = [0x00, 0x00, 0x11, 0xbe];
+
+/// The answer of a BMC to a presence ping
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Pong {
+    /// If IPMI is supported, as opposed to ASF only
+    pub ipmi: bool,
+    pub latency: Duration,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AuthCaps {
+    pub channel: u8,
+    /// The IPMI 1.5 authentication types of the admin privilege, e.g. MD5
+    pub auth_types: Vec<&'static str>,
+    pub ipmi15: bool,
+    pub ipmi20: bool,
+    pub per_message_auth: bool,
+    pub user_level_auth: bool,
+    pub non_null_users: bool,
+    pub null_users: bool,
+    pub anonymous: bool,
+}
+
+/// The IP of a host, zero padded dotted quads included
+pub fn addr(ip: &str) -> Result<IpAddr, String> {
+    let octets: Vec<u8> = ip.split('.').filter_map(|o| o.parse::<u8>().ok()).collect();
+    match octets[..] {
+        [a, b, c, d] if ip.split('.').count() == 4 => Ok(IpAddr::V4(Ipv4Addr::new(a, b, c, d))),
+        _ => ip.parse::<IpAddr>().map_err(|_| format!("invalid IP {}", ip)),
+    }
+}
+
+pub fn presence_ping(tag: u8) -> [u8; 12] {
+    // RMCP version 1.0, no ACK, class ASF; then the ASF message of type 80h without data
+    [0x06, 0x00, 0xff, 0x06, ASF_IANA[0], ASF_IANA[1], ASF_IANA[2], ASF_IANA[3], 0x80, tag, 0x00, 0x00]
+}
+
+/// Parse a presence pong to the ping with the tag, the latency is left for the caller
+pub fn parse_pong(packet: &[u8], tag: u8) -> Option<bool> {
+    match packet {
+        [0x06, _, _, 0x06, i0, i1, i2, i3, 0x40, t, _, len, data @ ..] if [*i0, *i1, *i2, *i3] == ASF_IANA && *t == tag && *len >= 16 && data.len() >= 16 => {
+            Some(data[8] & 0x80 != 0)
+        },
+        _ => None,
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg()
+}
+
+/// Get Channel Authentication Capabilities of the current channel for the admin privilege, with the IPMI 2.0 data
+pub fn auth_caps_request(seq: u8) -> Vec<u8> {
+    let header = [0x20, 0x06 << 2];
+    let body = [0x81, seq << 2, 0x38, 0x8e, 0x04];
+    let mut message = header.to_vec();
+    message.push(checksum(&header));
+    message.extend_from_slice(&body);
+    message.push(checksum(&body));
+
+    // RMCP class IPMI, then the IPMI 1.5 session header out of session: auth type none, sequence 0 and session ID 0
+    let mut packet = vec![0x06, 0x00, 0xff, 0x07, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, message.len() as u8];
+    packet.extend(message);
+    packet
+}
+
+pub fn parse_auth_caps(packet: &[u8]) -> Result<AuthCaps, String> {
+    // RMCP header, session header of 10 bytes without auth code, then the message from the BMC
+    let message = match packet {
+        [0x06, _, _, 0x07, 0x00, ..] if packet.len() > 14 => &packet[14..],
+        [0x06, _, _, 0x07, ..] => return Err(String::from("unexpected authenticated response")),
+        _ => return Err(String::from("not an IPMI response")),
+    };
+    if message.len() < 7 || message[5] != 0x38 {
+        return Err(String::from("not a Get Channel Authentication Capabilities response"));
+    }
+    let data = &message[6..];
+    match data.first() {
+        Some(0x00) if data.len() >= 5 => {},
+        Some(0x00) => return Err(format!("truncated response of {} bytes", data.len())),
+        Some(code) => return Err(format!("completion code {:02x}h", code)),
+        None => return Err(String::from("no completion code")),
+    }
+    let names = [(0x01, "NONE"), (0x02, "MD2"), (0x04, "MD5"), (0x10, "PASSWORD"), (0x20, "OEM")];
+    Ok(AuthCaps {
+        channel: data[1] & 0x0f,
+        auth_types: names.iter().filter(|(bit, _)| data[2] & bit != 0).map(|(_, name)| *name).collect(),
+        // without the extended data only IPMI 1.5 is supported
+        ipmi15: data[2] & 0x80 == 0 || data[4] & 0x01 != 0,
+        ipmi20: data[2] & 0x80 != 0 && data[4] & 0x02 != 0,
+        per_message_auth: data[3] & 0x10 == 0,
+        user_level_auth: data[3] & 0x08 == 0,
+        non_null_users: data[3] & 0x04 != 0,
+        null_users: data[3] & 0x02 != 0,
+        anonymous: data[3] & 0x01 != 0,
+    })
+}
+
+/// Send the packet and wait for the answer the parser takes, once more if there is none in time
+fn exchange<T>(ip: &str, packet: &[u8], timeout: Duration, parse: impl Fn(&[u8]) -> Option<T>) -> Result<(T, Duration), String> {
+    let addr = SocketAddr::new(addr(ip)?, PORT);
+    let local: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0".parse().unwrap() } else { "[::]:0".parse().unwrap() };
+    let socket = UdpSocket::bind(local).map_err(|e| e.to_string())?;
+    socket.connect(addr).map_err(|e| e.to_string())?;
+    let mut buffer = [0u8; 512];
+    for _ in 0..2 {
+        let start = Instant::now();
+        socket.send(packet).map_err(|e| e.to_string())?;
+        while let Some(left) = timeout.checked_sub(start.elapsed()).filter(|left| !left.is_zero()) {
+            socket.set_read_timeout(Some(left)).map_err(|e| e.to_string())?;
+            match socket.recv(&mut buffer) {
+                Ok(n) => if let Some(answer) = parse(&buffer[..n]) {
+                    return Ok((answer, start.elapsed()));
+                },
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+    Err(format!("no answer within {}ms", timeout.as_millis()))
+}
+
+pub fn ping(ip: &str, timeout: Duration) -> Result<Pong, String> {
+    let tag = rand::random::<u8>() & 0xfe;
+    exchange(ip, &presence_ping(tag), timeout, |packet| parse_pong(packet, tag)).map(|(ipmi, latency)| Pong {ipmi, latency})
+}
+
+pub fn auth_caps(ip: &str, timeout: Duration) -> Result<AuthCaps, String> {
+    let (caps, _) = exchange(ip, &auth_caps_request(rand::random::<u8>() & 0x3f), timeout, |packet| Some(parse_auth_caps(packet)))?;
+    caps
+}
+
+#[test]
+fn rmcp_pong() {
+    assert_eq!(presence_ping(0x12), [0x06, 0x00, 0xff, 0x06, 0x00, 0x00, 0x11, 0xbe, 0x80, 0x12, 0x00, 0x00]);
+    let pong = [
+        0x06, 0x00, 0xff, 0x06, 0x00, 0x00, 0x11, 0xbe, 0x40, 0x12, 0x00, 0x10,
+        0x00, 0x00, 0x11, 0xbe, 0x00, 0x00, 0x00, 0x00, 0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(parse_pong(&pong, 0x12), Some(true));
+    assert_eq!(parse_pong(&pong, 0x13), None);
+    assert_eq!(parse_pong(&pong[..20], 0x12), None);
+
+    assert_eq!(addr("010.245.038.001"), Ok(IpAddr::V4(Ipv4Addr::new(10, 245, 38, 1))));
+    assert!(addr("fe80::1").is_ok());
+    assert!(addr("10.245.38").is_err());
+}
+
+#[test]
+fn rmcp_auth_caps() {
+    let request = auth_caps_request(1);
+    assert_eq!(request, vec![0x06, 0x00, 0xff, 0x07, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0x09, 0x20, 0x18, 0xc8, 0x81, 0x04, 0x38, 0x8e, 0x04, 0xb1]);
+
+    // Supermicro X11: MD2, MD5 and PASSWORD, IPMI 1.5 and 2.0, non-null users only
+    let response = [
+        0x06, 0x00, 0xff, 0x07, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+        0x81, 0x1c, 0x63, 0x20, 0x04, 0x38, 0x00, 0x01, 0x96, 0x04, 0x03, 0x00, 0x00, 0x00, 0x00, 0x0f,
+    ];
+    let caps = parse_auth_caps(&response).unwrap();
+    assert_eq!(caps, AuthCaps {
+        channel: 1, auth_types: vec!["MD2", "MD5", "PASSWORD"], ipmi15: true, ipmi20: true,
+        per_message_auth: true, user_level_auth: true, non_null_users: true, null_users: false, anonymous: false,
+    });
+    let mut failed = response;
+    failed[20] = 0xcc;
+    assert_eq!(parse_auth_caps(&failed), Err(String::from("completion code cch")));
+}
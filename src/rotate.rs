@@ -0,0 +1,274 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Password rotation of the user of the hosts on the BMCs, kept in the `rotations` table until the host row is updated
+
+use structopt::StructOpt;
+
+use crate::{bmc_user, mc, password, profile, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub struct RotateOpts {
+    /// The index of the host, the current host or the hosts with the tag by default
+    id: Option<i64>,
+    /// The new password, the same for all the hosts
+    #[structopt(short, long, required_unless = "generate", conflicts_with = "generate")]
+    password: Option<String>,
+    /// Generate a new password of the password policy for each host
+    #[structopt(short, long)]
+    generate: bool,
+    /// The LAN channel to find the user ID on
+    #[structopt(short, long, default_value = "1")]
+    channel: u8,
+    /// Rotate without asking for confirmation
+    #[structopt(short, long)]
+    yes: bool,
+    #[structopt(flatten)]
+    group: Group,
+}
+
+/// The result of rotating the password of a host
+#[derive(Debug, PartialEq)]
+struct Rotation {
+    /// The password the BMC is known to accept afterwards, none if it accepts neither the old nor the new one
+    pswd: Option<String>,
+    /// Why the password is not rotated
+    error: Option<String>,
+}
+
+/// The pending rotations, by host ID, with the new password and when they were started
+fn pending(connection: &sqlite::Connection) -> Vec<(i64, String, i64)> {
+    let mut statement = connection.prepare("SELECT host_id, pswd, started FROM rotations ORDER BY host_id").unwrap();
+    let mut rotations = Vec::new();
+    while let sqlite::State::Row = statement.next().unwrap() {
+        rotations.push((statement.read::<i64>(0).unwrap(), statement.read::<String>(1).unwrap(), statement.read::<i64>(2).unwrap()));
+    }
+    rotations
+}
+
+/// Keep the new password before it is set on the BMC, to find a host left with it
+fn start(connection: &sqlite::Connection, host_id: i64, pswd: &str, time: i64) {
+    let mut statement = connection.prepare("INSERT OR REPLACE INTO rotations (host_id, pswd, started) VALUES (?, ?, ?)").unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.bind(2, pswd).unwrap();
+    statement.bind(3, time).unwrap();
+    statement.next().unwrap();
+}
+
+/// Update the host to the password the BMC accepts and end the rotation, in one transaction
+fn finish(connection: &sqlite::Connection, host_id: i64, pswd: &str, time: i64) {
+    connection.execute("BEGIN").unwrap();
+    password::record(connection, host_id, pswd, time);
+    let mut statement = connection.prepare("UPDATE hosts SET pswd=? WHERE id=?").unwrap();
+    statement.bind(1, pswd).unwrap();
+    statement.bind(2, host_id).unwrap();
+    statement.next().unwrap();
+    let mut statement = connection.prepare("DELETE FROM rotations WHERE host_id=?").unwrap();
+    statement.bind(1, host_id).unwrap();
+    statement.next().unwrap();
+    connection.execute("COMMIT").unwrap();
+}
+
+/// Set the new password of the user of the host on the BMC, and log in with it
+fn rotate(host: &Login, new: &str, channel: u8) -> Rotation {
+    let with = |pswd: &str| Login {host: Host {pswd: String::from(pswd), ..host.host.clone()}, ..host.clone()};
+    let accepts = |pswd: &str| mc::device_id(&with(pswd)).is_ok();
+    let failed = |pswd: Option<&str>, error: String| Rotation {pswd: pswd.map(String::from), error: Some(error)};
+
+    // a rotation left midway may have set the new password on the BMC already
+    let (current, users) = match bmc_user::list(host, channel) {
+        Ok(users) => (host.pswd.as_str(), users),
+        Err(e) => match bmc_user::list(&with(new), channel) {
+            Ok(users) => (new, users),
+            // the BMC may not answer, so which password it has is not known and the rotation is left pending
+            Err(_) => return failed(None, e),
+        },
+    };
+    let Some(id) = users.iter().find(|u| u.name == host.user).map(|u| u.id) else {
+        return failed(Some(current), format!("no user {} on channel {}", host.user, channel));
+    };
+    if let Err(e) = bmc_user::set_password(&with(current), id, new) {
+        // the password may be set even so, e.g. if the response is lost
+        if !accepts(new) {
+            return failed(Some(current).filter(|p| accepts(p)), e);
+        }
+    }
+    if accepts(new) {
+        Rotation {pswd: Some(String::from(new)), error: None}
+    } else if accepts(current) {
+        failed(Some(current), String::from("the new password is not accepted, the old one still is"))
+    } else {
+        failed(None, String::from("neither the new nor the old password is accepted"))
+    }
+}
+
+pub fn run(connection: &sqlite::Connection, opts: RotateOpts) {
+    let hosts = match opts.id {
+        Some(id) => Host::by_id(connection, id).map(|host| (id, host)).into_iter().collect(),
+        None => Host::group_or_help(connection, opts.group.tag.as_deref()),
+    };
+    if hosts.is_empty() {
+        if opts.id.is_some() {
+            println!("Please list and find available <index of host>:");
+            println!("    ipmi.exe host list");
+        }
+        return;
+    }
+    // a host inheriting the password of its profile is not to be left with one of its own, out of the profile
+    let count = hosts.len();
+    let hosts: Vec<(i64, Login)> = hosts.into_iter().filter(|(id, host)| match profile::inherited(connection, *id) {
+        Some(profile) => {
+            println!("{:>04} {:<15} skipped: the password is inherited from profile {}", id, host.ip, profile);
+            false
+        },
+        None => true,
+    }).collect();
+    if hosts.len() < count {
+        println!("Please detach the hosts from the profile to rotate their own password, or set the one of the profile:");
+        println!("    ipmi.exe host profile detach <index of host>");
+        println!("    ipmi.exe host profile set <profile> -p <pswd>");
+    }
+    if hosts.is_empty() {
+        return;
+    }
+    if !opts.yes && !crate::confirm(&format!("Rotate the password of the user of {} host(s)?", hosts.len())) {
+        return;
+    }
+
+    let policy = password::Policy::load(connection);
+    if let Some(Err(e)) = opts.password.as_deref().map(|p| policy.check(p)) {
+        println!("{}", e);
+        return;
+    }
+    let pending = pending(connection);
+    let time = crate::now();
+    let mut rotations = Vec::new();
+    for (id, host) in hosts {
+        // a host left mid-rotation is rotated to the password kept, which it may have already
+        let new = match pending.iter().find(|(i, ..)| *i == id) {
+            Some((_, pswd, _)) => pswd.clone(),
+            None if opts.generate => std::iter::repeat_with(|| policy.generate()).find(|p| !password::used(connection, id, p, policy.history)).unwrap(),
+            None => match opts.password.clone().unwrap_or_default() {
+                pswd if password::used(connection, id, &pswd, policy.history) || pswd == host.pswd => {
+                    println!("{:>04} {:<15} failed: the password was used before", id, host.ip);
+                    continue;
+                },
+                pswd => pswd,
+            },
+        };
+        start(connection, id, &new, time);
+        rotations.push((id, host, new));
+    }
+
+    let channel = opts.channel;
+    let results = crate::parallel(rotations, opts.group.jobs, |(id, host, new)| {
+        let rotation = rotate(&host, &new, channel);
+        (id, host, rotation)
+    });
+    for (id, host, rotation) in &results {
+        match rotation {
+            Rotation {pswd: Some(pswd), error: None} => {
+                finish(connection, *id, pswd, crate::now());
+                println!("{:>04} {:<15} password of {} rotated", id, host.ip, host.user);
+            },
+            Rotation {pswd: Some(pswd), error: Some(e)} => {
+                finish(connection, *id, pswd, crate::now());
+                println!("{:>04} {:<15} failed: {}", id, host.ip, e);
+            },
+            Rotation {pswd: None, error} => {
+                println!("{:>04} {:<15} failed: {}", id, host.ip, error.as_deref().unwrap_or_default());
+            },
+        }
+    }
+
+    let pending = self::pending(connection);
+    if !pending.is_empty() {
+        println!();
+        println!("{} host(s) left mid-rotation, with the new password kept to rotate them to once they answer:", pending.len());
+        for (id, _, started) in &pending {
+            let ip = Host::by_id(connection, *id).map(|h| h.host.ip).unwrap_or_default();
+            println!("{:>04} {:<15} since {}", id, ip, crate::datetime(*started));
+        }
+        println!("    ipmi.exe host rotate <index of host> --generate --yes");
+    }
+}
+
+#[test]
+fn rotate_pending() {
+    let db_name = "rotate.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database
+    };
+    std::fs::remove_file(&database).unwrap_or(());
+
+    {
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '10.245.38.1', 'admin', 'admin')").unwrap();
+        connection.execute("INSERT INTO hosts VALUES (2, 0, '10.245.38.2', 'admin', 'admin')").unwrap();
+
+        start(&connection, 1, "Rotated1", 1000);
+        start(&connection, 2, "Rotated2", 1000);
+        start(&connection, 2, "Rotated3", 2000);
+        assert_eq!(pending(&connection), vec![(1, String::from("Rotated1"), 1000), (2, String::from("Rotated3"), 2000)]);
+
+        finish(&connection, 1, "Rotated1", 3000);
+        assert_eq!(pending(&connection), vec![(2, String::from("Rotated3"), 2000)]);
+        assert_eq!(Host::by_id(&connection, 1).unwrap().pswd, "Rotated1");
+        assert_eq!(Host::by_id(&connection, 2).unwrap().pswd, "admin");
+        assert!(password::used(&connection, 1, "Rotated1", 1));
+    }
+    std::fs::remove_file(&database).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn rotate_round_trip() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // an ipmitool standing in for a BMC, which takes the password it keeps to log in and lets it be set
+    let bin = crate::datadir("rotate-bmc");
+    std::fs::create_dir_all(&bin).unwrap();
+    let ipmitool = bin.join("ipmitool");
+    std::fs::write(&ipmitool, r#"#!/bin/sh
+bmc=$(dirname "$0")
+while [ $# -gt 0 ]; do
+    case $1 in
+        -P) pswd=$2; shift 2;;
+        -I|-H|-U|-C|-S) shift 2;;
+        *) break;;
+    esac
+done
+[ "$pswd" = "$(cat "$bmc/pswd")" ] || { echo "Unable to establish IPMI v2 / RMCP+ session" >&2; exit 1; }
+case "$1 $2 $3" in
+    "user list 1") printf '%-4s%-17s%-8s%-11s%-11s%s\n' ID Name Callin "Link Auth" "IPMI Msg" "Channel Priv Limit" 2 admin true false true ADMINISTRATOR;;
+    "user set password") printf '%s\n' "$5" > "$bmc/pswd";;
+    "raw 0x06 0x01") echo " 20 01 01 71 02 bf 7c 2a 00 09 08";;
+esac
+"#).unwrap();
+    std::fs::set_permissions(&ipmitool, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::write(bin.join("pswd"), "Rack12-admin\n").unwrap();
+    std::env::set_var("PATH", format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default()));
+
+    // case: the new password is set and logged in with the same, even with a symbol the shell takes for a comment
+    let host = Login::from(Host {ip: String::from("10.245.38.1"), user: String::from("admin"), pswd: String::from("Rack12-admin")});
+    assert_eq!(rotate(&host, "#Rotated-12", 1), Rotation {pswd: Some(String::from("#Rotated-12")), error: None});
+    assert_eq!(std::fs::read_to_string(bin.join("pswd")).unwrap(), "#Rotated-12\n");
+
+    // case: a rotation left midway, with the new password set on the BMC already
+    assert_eq!(rotate(&host, "#Rotated-12", 1), Rotation {pswd: Some(String::from("#Rotated-12")), error: None});
+
+    // case: neither password logs in, so none is known to update the host to
+    assert_eq!(rotate(&host, "Rotated-13", 1).pswd, None);
+
+    std::fs::remove_dir_all(&bin).unwrap();
+}
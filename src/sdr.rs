@@ -0,0 +1,218 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! SDR repository cache of the hosts and parser of its records, see IPMI 2.0 section 43
+
+use std::fs;
+use std::path;
+use structopt::StructOpt;
+
+use crate::{mc, sel_record, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub enum SdrCommand {
+    /// Dump the SDR repository of the host(s) into the cache, once per firmware version
+    Dump {
+        /// Dump again even if the cache of the firmware version exists
+        #[structopt(short, long)]
+        force: bool,
+        #[structopt(flatten)]
+        group: Group,
+    },
+    /// List the sensor and FRU locator records of the current host cache or a dump
+    List {
+        /// The dump to list instead of the current host cache
+        file: Option<path::PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    /// Full (01h) or compact (02h) sensor record
+    Sensor {
+        id: u16,
+        kind: u8,
+        /// The slave address or software ID of the sensor owner, as the generator ID of its events
+        owner: u8,
+        number: u8,
+        entity: (u8, u8),
+        sensor_type: u8,
+        event_type: u8,
+        name: String,
+    },
+    /// FRU device locator (11h)
+    FruLocator {
+        id: u16,
+        device: u8,
+        entity: (u8, u8),
+        name: String,
+    },
+}
+
+/// The ID string of a record, 8-bit ASCII is the only type in practice
+fn id_string(body: &[u8], at: usize) -> String {
+    let Some(tl) = body.get(at) else {
+        return String::new();
+    };
+    let end = (at + 1 + (tl & 0x1f) as usize).min(body.len());
+    String::from_utf8_lossy(&body[at + 1..end]).trim_end_matches('\0').trim().to_string()
+}
+
+/// Parse the records of an SDR repository dump (`sdr dump`), other record types are skipped
+pub fn parse(dump: &[u8]) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    let mut i = 0;
+    while i < dump.len() {
+        let header = dump.get(i..i + 5).ok_or(format!("truncated SDR record header at {}", i))?;
+        let id = u16::from_le_bytes([header[0], header[1]]);
+        let record = dump.get(i..i + 5 + header[4] as usize).ok_or(format!("truncated SDR record {:04x}h", id))?;
+        match header[3] {
+            kind @ (0x01 | 0x02) if record.len() >= 16 => {
+                records.push(Record::Sensor {
+                    id, kind,
+                    owner: record[5],
+                    number: record[7],
+                    entity: (record[8], record[9]),
+                    sensor_type: record[12],
+                    event_type: record[13],
+                    name: id_string(record, if kind == 0x01 { 47 } else { 31 }),
+                });
+            },
+            0x11 if record.len() >= 16 => {
+                records.push(Record::FruLocator {id, device: record[6], entity: (record[12], record[13]), name: id_string(record, 15)});
+            },
+            _ => {},
+        }
+        i += record.len();
+    }
+    Ok(records)
+}
+
+/// The name of the sensor of an event generator and sensor number, e.g. of a SEL record
+pub fn sensor_name(records: &[Record], generator: u8, sensor: u8) -> Option<&str> {
+    records.iter().find_map(|r| match r {
+        Record::Sensor {owner, number, name, ..} if *owner == generator && *number == sensor => Some(name.as_str()),
+        _ => None,
+    })
+}
+
+fn cache_dir(ip: &str) -> path::PathBuf {
+    crate::datahome().join(".ipmi").join("sdr").join(ip.replace(':', "-"))
+}
+
+/// The cache file of the firmware version of the host
+fn file(ip: &str, firmware: &str) -> path::PathBuf {
+    let firmware: String = firmware.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' }).collect();
+    cache_dir(ip).join(format!("{}.sdr", firmware))
+}
+
+/// The cache of the current firmware version of the host, none if not dumped yet or the BMC does not tell its version
+pub fn cache(host: &Login) -> Option<path::PathBuf> {
+    // the BMC is asked only if any firmware version of the host was dumped
+    if !cache_dir(&host.ip).is_dir() {
+        return None;
+    }
+    Some(file(&host.ip, &mc::device_id(host).ok()?.firmware)).filter(|file| file.is_file())
+}
+
+/// The records of the host cache, none if not dumped yet
+pub fn load(host: &Login) -> Vec<Record> {
+    cache(host).and_then(|file| fs::read(file).ok()).and_then(|dump| parse(&dump).ok()).unwrap_or_default()
+}
+
+/// Dump the SDR repository to the cache of the firmware version, `Ok(None)` if already there
+fn dump(host: &Login, force: bool) -> Result<Option<(String, usize)>, String> {
+    let id = mc::device_id(host)?;
+    fs::create_dir_all(cache_dir(&host.ip)).map_err(|e| e.to_string())?;
+    let file = file(&host.ip, &id.firmware);
+    if file.is_file() && !force {
+        return Ok(None);
+    }
+    host.run(&["sdr", "dump", &format!("\"{}\"", file.display())])?;
+    let records = fs::read(&file).map_err(|e| e.to_string()).and_then(|dump| parse(&dump))?;
+    Ok(Some((id.firmware, records.len())))
+}
+
+fn print(records: &[Record]) {
+    println!("{:<6} {:<8} {:<6} {:<6} {:<7} {:<25} Name", "Id", "Record", "Owner", "Number", "Entity", "Sensor Type");
+    for r in records {
+        match r {
+            Record::Sensor {id, kind, owner, number, entity, sensor_type, name, ..} => {
+                let kind = if *kind == 0x01 { "full" } else { "compact" };
+                let sensor_type = sel_record::sensor_type_name(*sensor_type).map_or(format!("{:02x}h", sensor_type), String::from);
+                println!("{:<6} {:<8} {:<6} {:<6} {:<7} {:<25} {}", format!("{:04x}", id), kind, format!("{:02x}h", owner),
+                    format!("{:02x}h", number), format!("{}.{}", entity.0, entity.1), sensor_type, name);
+            },
+            Record::FruLocator {id, device, entity, name} => {
+                println!("{:<6} {:<8} {:<6} {:<6} {:<7} {:<25} {}", format!("{:04x}", id), "fru", "", format!("{:02x}h", device),
+                    format!("{}.{}", entity.0, entity.1), "", name);
+            },
+        }
+    }
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: SdrCommand) {
+    match cmd {
+        SdrCommand::Dump{force, group} => {
+            let hosts = Host::group_or_help(connection, group.tag.as_deref());
+            if hosts.is_empty() {
+                return;
+            }
+
+            let results = crate::parallel(hosts, group.jobs, |(id, host)| {
+                let result = dump(&host, force);
+                (id, host, result)
+            });
+            for (id, host, result) in &results {
+                match result {
+                    Ok(Some((firmware, n))) => println!("{:>04} {:<15} {} records of firmware {} cached", id, host.ip, n, firmware),
+                    Ok(None) => println!("{:>04} {:<15} cached already", id, host.ip),
+                    Err(e) => println!("{:>04} {:<15} failed: {}", id, host.ip, e),
+                }
+            }
+        },
+        SdrCommand::List{file} => {
+            let file = match file {
+                Some(file) => file,
+                None => match Host::group_or_help(connection, None).pop().map(|(_, host)| cache(&host).ok_or(host)) {
+                    Some(Ok(file)) => file,
+                    Some(Err(host)) => {
+                        println!("No SDR cache of the current firmware of {}, dump it with command:", host.ip);
+                        println!("    ipmi.exe sdr dump");
+                        return;
+                    },
+                    None => return,
+                },
+            };
+            match fs::read(&file).map_err(|e| e.to_string()).and_then(|dump| parse(&dump)) {
+                Ok(records) => print(&records),
+                Err(e) => println!("Unable to parse {}: {}", file.display(), e),
+            }
+        },
+    }
+}
+
+#[test]
+fn sdr_parse() {
+    let records = parse(&fs::read("tests/fixtures/sdr_dump.bin").unwrap()).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0], Record::Sensor {
+        id: 1, kind: 0x01, owner: 0x20, number: 0x30, entity: (3, 1), sensor_type: 0x01, event_type: 0x01, name: String::from("CPU1 Temp"),
+    });
+    assert_eq!(records[1], Record::Sensor {
+        id: 2, kind: 0x02, owner: 0x20, number: 0x51, entity: (10, 1), sensor_type: 0x08, event_type: 0x6f, name: String::from("PS1 Status"),
+    });
+    assert_eq!(records[2], Record::FruLocator {id: 4, device: 1, entity: (10, 1), name: String::from("PS1 FRU")});
+
+    assert_eq!(sensor_name(&records, 0x20, 0x51), Some("PS1 Status"));
+    assert_eq!(sensor_name(&records, 0x20, 0x52), None);
+    assert!(parse(&[0x01, 0x00, 0x51, 0x01]).is_err());
+}
@@ -0,0 +1,571 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+use std::fs;
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::{sdr, sel_record, Format, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub enum SelCommand {
+    /// List the System Event Log entries
+    List {
+        /// Only the events of the last <since>, e.g. 30m, 24h or 7d
+        #[structopt(short, long, parse(try_from_str = crate::seconds))]
+        since: Option<u64>,
+        /// Only the events of the severity or above: info, warning or critical
+        #[structopt(short = "S", long)]
+        severity: Option<Severity>,
+        /// Only the events of the sensors with the name or type matching the glob
+        #[structopt(short = "n", long)]
+        sensor: Option<String>,
+        /// Output format: table, json or csv
+        #[structopt(short, long, default_value = "table")]
+        format: Format,
+        /// List the events collected by `sel new` from the database instead of the BMC
+        #[structopt(long)]
+        stored: bool,
+        /// Decode the raw SEL records natively instead of parsing the ipmitool text
+        #[structopt(long, conflicts_with = "stored")]
+        native: bool,
+        #[structopt(flatten)]
+        group: Group,
+    },
+    /// List the events since the last check of the host(s) and collect them into the database
+    New {
+        /// Output format: table, json or csv
+        #[structopt(short, long, default_value = "table")]
+        format: Format,
+        #[structopt(flatten)]
+        group: Group,
+    },
+    /// Decode the raw SEL records of a `sel writeraw` dump or `raw 0x0a 0x43` output
+    Decode {
+        file: std::path::PathBuf,
+        /// The SDR repository dump (`sdr dump`) to name the sensors with
+        #[structopt(long)]
+        sdr: Option<std::path::PathBuf>,
+        /// Output format: table, json or csv
+        #[structopt(short, long, default_value = "table")]
+        format: Format,
+    },
+    /// Back up and clear the System Event Log
+    Clear {
+        /// Clear without asking for confirmation
+        #[structopt(short, long)]
+        yes: bool,
+        #[structopt(flatten)]
+        group: Group,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl std::str::FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "info"     => Ok(Severity::Info),
+            "warning"  => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _          => Err(format!("invalid severity {}, expect one of info, warning or critical", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(match self {
+            Severity::Info     => "info",
+            Severity::Warning  => "warning",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+/// An entry of `ipmitool sel elist`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    pub id: u16,
+    /// Seconds since the unix epoch, `None` if logged before the BMC clock was set
+    #[serde(skip_serializing)]
+    pub time: Option<i64>,
+    pub sensor_type: String,
+    pub sensor: String,
+    pub description: String,
+    /// `true` if asserted, `false` if deasserted
+    pub asserted: bool,
+    pub severity: Severity,
+}
+
+/// The sensor types as ipmitool names them by the type code from 01h, see IPMI 2.0 table 42-3
+pub const SENSOR_TYPES: &[&str] = &[
+    "Temperature", "Voltage", "Current", "Fan", "Physical Security", "Platform Security", "Processor",
+    "Power Supply", "Power Unit", "Cooling Device", "Other", "Memory", "Drive Slot / Bay", "POST Memory Resize",
+    "System Firmwares", "Event Logging Disabled", "Watchdog1", "System Event", "Critical Interrupt", "Button",
+    "Module / Board", "Microcontroller", "Add-in Card", "Chassis", "Chip Set", "Other FRU", "Cable / Interconnect",
+    "Terminator", "System Boot Initiated", "Boot Error", "OS Boot", "OS Critical Stop", "Slot / Connector",
+    "System ACPI Power State", "Watchdog2", "Platform Alert", "Entity Presence", "Monitor ASIC", "LAN",
+    "Management Subsys Health", "Battery", "Session Audit", "Version Change", "FRU State",
+];
+
+/// Split `Temperature CPU Temp` into the sensor type and name, by the longest known type
+fn sensor_type(field: &str) -> (String, String) {
+    // newer ipmitool names 0Fh by the spec
+    let known = SENSOR_TYPES.iter().chain(["System Firmware Progress"].iter())
+        .filter(|t| field.starts_with(*t) && (field.len() == t.len() || field[t.len()..].starts_with(' ')))
+        .max_by_key(|t| t.len());
+    match known {
+        Some(t) => (String::from(*t), String::from(field[t.len()..].trim())),
+        None => match field.strip_prefix("OEM record ") {
+            Some(kind) => (String::from("OEM"), String::from(kind)),
+            None => (String::new(), String::from(field)),
+        },
+    }
+}
+
+/// The severity by the event description, deasserted events are info as the condition is gone
+pub fn severity(description: &str, asserted: bool) -> Severity {
+    let d = description.to_lowercase();
+    let any = |words: &[&str]| words.iter().any(|w| d.contains(w));
+    if !asserted {
+        Severity::Info
+    } else if any(&["non-critical", "correctable ecc", "predictive", "degraded", "throttl", "intrusion"]) && !any(&["uncorrectable"]) {
+        Severity::Warning
+    } else if any(&["critical", "non-recoverable", "failure", "fault", "uncorrectable", "ierr", "thermal trip", "lost", "fatal", "power off"]) {
+        Severity::Critical
+    } else {
+        Severity::Info
+    }
+}
+
+/// Parse the date and time fields of `sel elist`, as local time of the ipmitool host
+fn timestamp(date: &str, time: &str) -> Option<i64> {
+    use chrono::TimeZone;
+    let t = chrono::NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%m/%d/%Y %H:%M:%S").ok()?;
+    chrono::Local.from_local_datetime(&t).earliest().map(|t| t.timestamp())
+}
+
+/// Parse `ipmitool sel elist`, lines which are not an event are skipped
+pub fn parse(output: &str) -> Vec<Event> {
+    let mut events = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split('|').map(|f| f.trim()).collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        let id = match u16::from_str_radix(fields[0], 16) {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        let (sensor_type, sensor) = sensor_type(fields[3]);
+        let mut description = String::from(fields[4]);
+        let mut asserted = true;
+        for f in &fields[5..] {
+            match *f {
+                "Asserted"   => asserted = true,
+                "Deasserted" => asserted = false,
+                ""           => {},
+                _            => description.push_str(&format!(" ({})", f)),
+            }
+        }
+        events.push(Event {
+            id,
+            time: timestamp(fields[1], fields[2]),
+            sensor_type,
+            sensor,
+            severity: severity(&description, asserted),
+            description,
+            asserted,
+        });
+    }
+    events
+}
+
+impl Event {
+    pub fn matches(&self, from: Option<i64>, severity: Option<Severity>, sensor: Option<&str>) -> bool {
+        from.is_none_or(|from| self.time.is_some_and(|t| t >= from))
+            && severity.is_none_or(|s| self.severity >= s)
+            && sensor.is_none_or(|s| crate::glob(s, &self.sensor) || crate::glob(s, &self.sensor_type))
+    }
+}
+
+/// The last event seen on a host
+#[derive(Debug, Clone, PartialEq)]
+struct Cursor {
+    record: u16,
+    time: Option<i64>,
+    sensor: String,
+    description: String,
+}
+
+impl Cursor {
+    fn of(e: &Event) -> Cursor {
+        Cursor { record: e.id, time: e.time, sensor: e.sensor.clone(), description: e.description.clone() }
+    }
+    fn load(connection: &sqlite::Connection, host_id: i64) -> Option<Cursor> {
+        let mut statement = connection.prepare("SELECT record, time, sensor, description FROM sel_cursors WHERE host_id=?").unwrap();
+        statement.bind(1, host_id).unwrap();
+        match statement.next().unwrap() {
+            sqlite::State::Row => Some(Cursor {
+                record: statement.read::<i64>(0).unwrap() as u16,
+                time: statement.read::<Option<i64>>(1).unwrap(),
+                sensor: statement.read::<String>(2).unwrap(),
+                description: statement.read::<String>(3).unwrap(),
+            }),
+            sqlite::State::Done => None,
+        }
+    }
+    fn save(&self, connection: &sqlite::Connection, host_id: i64) {
+        let mut statement = connection.prepare(
+            "INSERT OR REPLACE INTO sel_cursors(host_id, record, time, sensor, description, checked) VALUES (?, ?, ?, ?, ?, ?)"
+        ).unwrap();
+        statement.bind(1, host_id).unwrap();
+        statement.bind(2, self.record as i64).unwrap();
+        statement.bind(3, self.time).unwrap();
+        statement.bind(4, self.sensor.as_str()).unwrap();
+        statement.bind(5, self.description.as_str()).unwrap();
+        statement.bind(6, crate::now()).unwrap();
+        statement.next().unwrap();
+    }
+}
+
+/// The events after the cursor. If the cursor event is gone, the SEL was cleared or wrapped
+/// around and the record ids started over, then the events not older than the cursor are new.
+fn unseen<'a>(events: &'a [Event], cursor: Option<&Cursor>) -> &'a [Event] {
+    let cursor = match cursor {
+        Some(cursor) => cursor,
+        None => return events,
+    };
+    if let Some(i) = events.iter().rposition(|e| Cursor::of(e) == *cursor) {
+        return &events[i + 1..];
+    }
+    let from = events.iter().position(|e| match (e.time, cursor.time) {
+        (Some(t), Some(c)) => t >= c,
+        _ => false,
+    });
+    match from {
+        Some(i) => &events[i..],
+        // all the events are older, or the clock was never set, go by the record id at least
+        None => {
+            let i = events.iter().position(|e| e.id > cursor.record).unwrap_or(events.len());
+            &events[i..]
+        },
+    }
+}
+
+/// Store the events not collected yet, the events collected are returned
+fn collect(connection: &sqlite::Connection, host_id: i64, events: &[Event]) -> Vec<Event> {
+    let mut collected = Vec::new();
+    connection.execute("BEGIN").unwrap();
+    for e in events {
+        let mut statement = connection.prepare(
+            "SELECT 1 FROM sel_events WHERE host_id=? AND record=? AND time IS ? AND sensor=? AND description=?"
+        ).unwrap();
+        statement.bind(1, host_id).unwrap();
+        statement.bind(2, e.id as i64).unwrap();
+        statement.bind(3, e.time).unwrap();
+        statement.bind(4, e.sensor.as_str()).unwrap();
+        statement.bind(5, e.description.as_str()).unwrap();
+        if let sqlite::State::Row = statement.next().unwrap() {
+            continue;
+        }
+
+        let mut statement = connection.prepare(
+            "INSERT INTO sel_events(host_id, record, time, sensor_type, sensor, description, asserted, severity, collected) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        ).unwrap();
+        statement.bind(1, host_id).unwrap();
+        statement.bind(2, e.id as i64).unwrap();
+        statement.bind(3, e.time).unwrap();
+        statement.bind(4, e.sensor_type.as_str()).unwrap();
+        statement.bind(5, e.sensor.as_str()).unwrap();
+        statement.bind(6, e.description.as_str()).unwrap();
+        statement.bind(7, e.asserted as i64).unwrap();
+        statement.bind(8, e.severity.to_string().as_str()).unwrap();
+        statement.bind(9, crate::now()).unwrap();
+        statement.next().unwrap();
+        collected.push(e.clone());
+    }
+    if let Some(last) = events.last() {
+        Cursor::of(last).save(connection, host_id);
+    }
+    connection.execute("COMMIT").unwrap();
+    collected
+}
+
+/// The events collected by `sel new` of the host
+fn stored(connection: &sqlite::Connection, host_id: i64) -> Vec<Event> {
+    let mut statement = connection.prepare(
+        "SELECT record, time, sensor_type, sensor, description, asserted, severity FROM sel_events WHERE host_id=? ORDER BY ROWID ASC"
+    ).unwrap();
+    statement.bind(1, host_id).unwrap();
+    let mut events = Vec::new();
+    while let sqlite::State::Row = statement.next().unwrap() {
+        events.push(Event {
+            id: statement.read::<i64>(0).unwrap() as u16,
+            time: statement.read::<Option<i64>>(1).unwrap(),
+            sensor_type: statement.read::<String>(2).unwrap(),
+            sensor: statement.read::<String>(3).unwrap(),
+            description: statement.read::<String>(4).unwrap(),
+            asserted: statement.read::<i64>(5).unwrap() != 0,
+            severity: statement.read::<String>(6).unwrap().parse().unwrap_or(Severity::Info),
+        });
+    }
+    events
+}
+
+/// Print the events, each with the host it is from
+pub fn print(events: &[(&str, Event)], format: Format) {
+    let time = |e: &Event| match (e.time, format) {
+        (Some(t), Format::Table) => crate::datetime(t),
+        (Some(t), _) => crate::rfc3339(t),
+        (None, _) => String::from("Pre-Init"),
+    };
+    let direction = |e: &Event| if e.asserted { "Asserted" } else { "Deasserted" };
+    match format {
+        Format::Table => {
+            println!("{:<15}  {:>4}  {:<19}  {:<8}  {:<24}  {:<20}  {:<10}  Description", "Host", "Id", "Time", "Severity", "Sensor Type", "Sensor", "Direction");
+            for (host, e) in events {
+                println!(
+                    "{:<15}  {:>4x}  {:<19}  {:<8}  {:<24}  {:<20}  {:<10}  {}",
+                    host, e.id, time(e), e.severity, e.sensor_type, e.sensor, direction(e), e.description,
+                );
+            }
+        },
+        Format::Json => {
+            #[derive(Serialize)]
+            struct Entry<'a> {
+                host: &'a str,
+                time: String,
+                #[serde(flatten)]
+                event: &'a Event,
+            }
+            let entries: Vec<Entry> = events.iter().map(|(host, event)| Entry { host, time: time(event), event }).collect();
+            println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+        },
+        Format::Csv => {
+            println!("host,id,time,severity,sensor_type,sensor,direction,description");
+            for (host, e) in events {
+                println!(
+                    "{},{},{},{},{},{},{},{}",
+                    crate::csv(host), e.id, time(e), e.severity, crate::csv(&e.sensor_type), crate::csv(&e.sensor), direction(e), crate::csv(&e.description),
+                );
+            }
+        },
+    }
+}
+
+/// Save the SEL of the host under the data directory before it is cleared, the text and the raw records
+fn backup(host: &Login) -> Result<std::path::PathBuf, String> {
+    let elist = host.run(&["sel", "elist"])?;
+    let stem = format!("{}-{}", host.ip.replace(':', "_"), chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    let dir = crate::datadir("sel");
+    let text = dir.join(format!("{}.txt", stem));
+    fs::write(&text, elist).map_err(|e| e.to_string())?;
+    // not every BMC supports the raw dump, the text is enough to go on
+    let raw = dir.join(format!("{}.bin", stem));
+    let _ = host.run(&["sel", "writeraw", &format!("\"{}\"", raw.display())]);
+    Ok(text)
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: SelCommand) {
+    match cmd {
+        SelCommand::Decode{file, sdr, format} => {
+            let sdrs = match sdr.as_ref().map(|sdr| fs::read(sdr).map_err(|e| e.to_string()).and_then(|dump| sdr::parse(&dump))) {
+                Some(Ok(sdrs)) => sdrs,
+                Some(Err(e)) => {
+                    println!("Unable to parse the SDR dump: {}", e);
+                    return;
+                },
+                None => Vec::new(),
+            };
+            match fs::read(&file).map_err(|e| e.to_string()).and_then(|dump| sel_record::records(&dump)) {
+                Ok(records) => print(&records.iter().map(|r| ("", sel_record::decode_named(r, &sdrs))).collect::<Vec<_>>(), format),
+                Err(e) => println!("Unable to decode {}: {}", file.display(), e),
+            }
+        },
+        SelCommand::List{since, severity, sensor, format, stored: true, group, ..} => {
+            let hosts = Host::group_or_help(connection, group.tag.as_deref());
+            if hosts.is_empty() {
+                return;
+            }
+            let from = since.map(|s| crate::now() - s as i64);
+            let mut events = Vec::new();
+            for (id, host) in &hosts {
+                events.extend(stored(connection, *id).into_iter().filter(|e| e.matches(from, severity, sensor.as_deref())).map(|e| (host.ip.as_str(), e)));
+            }
+            print(&events, format);
+        },
+        SelCommand::List{since, severity, sensor, format, native, group, ..} => {
+            let hosts = Host::group_or_help(connection, group.tag.as_deref());
+            if hosts.is_empty() {
+                return;
+            }
+            let from = since.map(|s| crate::now() - s as i64);
+            let results = crate::parallel(hosts.iter().collect(), group.jobs, |(_, host)| {
+                if native {
+                    let sdrs = sdr::load(host);
+                    sel_record::fetch(host).map(|records| records.iter().map(|r| sel_record::decode_named(r, &sdrs)).collect())
+                } else {
+                    host.run(&["sel", "elist"]).map(|output| parse(&output))
+                }
+            });
+            let mut events = Vec::new();
+            for ((id, host), result) in hosts.iter().zip(results) {
+                match result {
+                    Ok(all) => events.extend(all.into_iter().filter(|e| e.matches(from, severity, sensor.as_deref())).map(|e| (host.ip.as_str(), e))),
+                    Err(e) => eprintln!("{:>04} {:<15} failed: {}", id, host.ip, e),
+                }
+            }
+            print(&events, format);
+        },
+        SelCommand::New{format, group} => {
+            let hosts = Host::group_or_help(connection, group.tag.as_deref());
+            if hosts.is_empty() {
+                return;
+            }
+            let results = crate::parallel(hosts.iter().collect(), group.jobs, |(_, host)| host.run(&["sel", "elist"]));
+            let mut events = Vec::new();
+            for ((id, host), result) in hosts.iter().zip(results) {
+                match result {
+                    Ok(output) => {
+                        let all = parse(&output);
+                        let cursor = Cursor::load(connection, *id);
+                        events.extend(collect(connection, *id, unseen(&all, cursor.as_ref())).into_iter().map(|e| (host.ip.as_str(), e)));
+                    },
+                    Err(e) => eprintln!("{:>04} {:<15} failed: {}", id, host.ip, e),
+                }
+            }
+            print(&events, format);
+        },
+        SelCommand::Clear{yes, group} => {
+            let hosts = Host::group_or_help(connection, group.tag.as_deref());
+            if hosts.is_empty() {
+                return;
+            }
+            for (id, host) in &hosts {
+                println!("{:>04} {:<15}", id, host.ip);
+            }
+            if !yes && !crate::confirm(&format!("Clear the SEL of the {} host(s) above?", hosts.len())) {
+                return;
+            }
+            crate::parallel(hosts.iter().collect(), group.jobs, |(id, host)| {
+                match backup(host) {
+                    Ok(file) => match host.run(&["sel", "clear"]) {
+                        Ok(_) => println!("{:>04} {:<15} cleared, backup saved to {}", id, host.ip, file.display()),
+                        Err(e) => println!("{:>04} {:<15} failed: {}", id, host.ip, e),
+                    },
+                    Err(e) => println!("{:>04} {:<15} not cleared, backup failed: {}", id, host.ip, e),
+                }
+            });
+        },
+    }
+}
+
+#[test]
+fn sel_parse() {
+    let events = parse(include_str!("../tests/fixtures/sel_elist_supermicro.txt"));
+    assert_eq!(events.len(), 7);
+    assert_eq!(events[1], Event {
+        id: 2, time: timestamp("06/01/2020", "08:00:05"), sensor_type: String::from("Power Supply"), sensor: String::from("PS1 Status"),
+        description: String::from("Failure detected ()"), asserted: true, severity: Severity::Critical,
+    });
+    assert_eq!((events[2].asserted, events[2].severity), (false, Severity::Info));
+    assert_eq!(events[3].description, "Upper Critical going high (Reading 100 > Threshold 99 degrees C)");
+    assert_eq!(events[3].severity, Severity::Critical);
+    assert_eq!((events[5].sensor_type.as_str(), events[5].sensor.as_str(), events[5].severity), ("Memory", "#0x87", Severity::Warning));
+    assert_eq!(events[6].severity, Severity::Warning);
+    assert_eq!(events[4].time.unwrap() - events[3].time.unwrap(), 30);
+
+    let events = parse(include_str!("../tests/fixtures/sel_elist_dell.txt"));
+    assert_eq!(events.len(), 6);
+    assert_eq!((events[0].id, events[0].time, events[0].sensor_type.as_str()), (1, None, "System Event"));
+    assert_eq!((events[1].id, events[1].sensor_type.as_str(), events[1].sensor.as_str(), events[1].severity), (0x1a, "Fan", "FAN MOD 1A RPM", Severity::Critical));
+    assert_eq!(events[2].severity, Severity::Critical);
+    assert_eq!(events[3].severity, Severity::Critical);
+    assert_eq!((events[4].sensor_type.as_str(), events[4].sensor.as_str(), events[4].severity), ("Drive Slot / Bay", "Drive 0", Severity::Info));
+    assert_eq!((events[5].sensor_type.as_str(), events[5].sensor.as_str(), events[5].description.as_str()), ("OEM", "c0", "0000000103 (2e0000ab00)"));
+}
+
+#[test]
+fn sel_filter() {
+    let events = parse(include_str!("../tests/fixtures/sel_elist_supermicro.txt"));
+    let count = |from, severity, sensor| events.iter().filter(|e| e.matches(from, severity, sensor)).count();
+    assert_eq!(count(None, None, None), 7);
+    assert_eq!(count(None, Some(Severity::Critical), None), 2);
+    assert_eq!(count(None, Some(Severity::Warning), None), 4);
+    assert_eq!(count(None, None, Some("temperature")), 2);
+    assert_eq!(count(None, None, Some("PS?*")), 2);
+    assert_eq!(count(timestamp("06/02/2020", "00:00:00"), None, None), 4);
+    // events before the clock was set have no time to compare
+    let events = parse(include_str!("../tests/fixtures/sel_elist_dell.txt"));
+    assert_eq!(events.iter().filter(|e| e.matches(Some(0), None, None)).count(), 5);
+}
+
+#[test]
+fn sel_unseen() {
+    let events = parse(include_str!("../tests/fixtures/sel_elist_supermicro.txt"));
+
+    // case: first check, all events are new
+    assert_eq!(unseen(&events, None).len(), 7);
+
+    // case: events after the cursor
+    let cursor = Cursor::of(&events[4]);
+    assert_eq!(unseen(&events, Some(&cursor)).iter().map(|e| e.id).collect::<Vec<_>>(), vec![6, 7]);
+    assert!(unseen(&events, Some(&Cursor::of(&events[6]))).is_empty());
+
+    // case: cleared, the ids started over and the cursor event is gone
+    let cleared = parse(&format!(
+        "{}\n{}\n",
+        "   1 | 06/03/2020 | 02:00:00 | Event Logging Disabled #0xff | Log area reset/cleared | Asserted",
+        "   2 | 06/03/2020 | 02:10:00 | Temperature CPU Temp | Upper Critical going high | Asserted",
+    ));
+    assert_eq!(unseen(&cleared, Some(&cursor)).iter().map(|e| e.id).collect::<Vec<_>>(), vec![1, 2]);
+
+    // case: wrapped around, the older events before the cursor time are skipped
+    let mut wrapped = events[2..5].to_vec();
+    wrapped.extend(cleared.clone());
+    let cursor = Cursor::of(&events[6]);
+    assert_eq!(unseen(&wrapped, Some(&cursor)).iter().map(|e| e.id).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn sel_collect() {
+    let db_name = "sel.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database.clone()
+    };
+    fs::remove_file(&database).unwrap_or(());
+
+    { // case: collect once and follow the cursor
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '000.000.000.000', 'admin', 'admin')").unwrap();
+        let events = parse(include_str!("../tests/fixtures/sel_elist_supermicro.txt"));
+
+        assert_eq!(collect(&connection, 1, unseen(&events[..5], Cursor::load(&connection, 1).as_ref())).len(), 5);
+        assert_eq!(Cursor::load(&connection, 1), Some(Cursor::of(&events[4])));
+        assert_eq!(collect(&connection, 1, unseen(&events, Cursor::load(&connection, 1).as_ref())).len(), 2);
+        // already collected events are not collected again
+        assert!(collect(&connection, 1, &events).is_empty());
+        assert_eq!(stored(&connection, 1), events);
+    }
+    fs::remove_file(&database).unwrap();
+}
@@ -0,0 +1,357 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+//! Decoder of the raw 16 bytes SEL records, see IPMI 2.0 section 32 and tables 42-1 to 42-3
+
+use crate::sel::{self, Event, SENSOR_TYPES};
+use crate::{sdr, Login};
+
+/// Timestamps up to 20000000h are relative to the BMC initialization
+const TIMESTAMP_PRE_INIT: u32 = 0x2000_0000;
+const TIMESTAMP_UNSPECIFIED: u32 = 0xffff_ffff;
+
+const THRESHOLD: &[&str] = &[
+    "Lower Non-critical going low", "Lower Non-critical going high", "Lower Critical going low", "Lower Critical going high",
+    "Lower Non-recoverable going low", "Lower Non-recoverable going high", "Upper Non-critical going low", "Upper Non-critical going high",
+    "Upper Critical going low", "Upper Critical going high", "Upper Non-recoverable going low", "Upper Non-recoverable going high",
+];
+
+/// Generic discrete offsets by event/reading type 02h to 0Ch
+fn generic(event_type: u8) -> &'static [&'static str] {
+    match event_type {
+        0x02 => &["Transition to Idle", "Transition to Active", "Transition to Busy"],
+        0x03 => &["State Deasserted", "State Asserted"],
+        0x04 => &["Predictive Failure Deasserted", "Predictive Failure Asserted"],
+        0x05 => &["Limit Not Exceeded", "Limit Exceeded"],
+        0x06 => &["Performance Met", "Performance Lags"],
+        0x07 => &[
+            "Transition to OK", "Transition to Non-critical from OK", "Transition to Critical from less severe",
+            "Transition to Non-recoverable from less severe", "Transition to Non-critical from more severe",
+            "Transition to Critical from Non-recoverable", "Transition to Non-recoverable", "Monitor", "Informational",
+        ],
+        0x08 => &["Device Removed / Device Absent", "Device Inserted / Device Present"],
+        0x09 => &["Device Disabled", "Device Enabled"],
+        0x0a => &[
+            "Transition to Running", "Transition to In Test", "Transition to Power Off", "Transition to On Line",
+            "Transition to Off Line", "Transition to Off Duty", "Transition to Degraded", "Transition to Power Save", "Install Error",
+        ],
+        0x0b => &[
+            "Fully Redundant", "Redundancy Lost", "Redundancy Degraded", "Non-redundant: Sufficient from Redundant",
+            "Non-redundant: Sufficient from Insufficient", "Non-redundant: Insufficient Resources",
+            "Redundancy Degraded from Fully Redundant", "Redundancy Degraded from Non-redundant",
+        ],
+        0x0c => &["D0 Power State", "D1 Power State", "D2 Power State", "D3 Power State"],
+        _ => &[],
+    }
+}
+
+/// Sensor-specific offsets (event/reading type 6Fh) by sensor type
+fn specific(sensor_type: u8) -> &'static [&'static str] {
+    match sensor_type {
+        0x05 => &[
+            "General Chassis intrusion", "Drive Bay intrusion", "I/O Card area intrusion", "Processor area intrusion",
+            "System unplugged from LAN", "Unauthorized dock", "FAN area intrusion",
+        ],
+        0x06 => &[
+            "Front Panel Lockout violation attempted", "Pre-boot password violation - user password",
+            "Pre-boot password violation - setup password", "Pre-boot password violation - network boot password",
+            "Other pre-boot password violation", "Out-of-band access password violation",
+        ],
+        0x07 => &[
+            "IERR", "Thermal Trip", "FRB1/BIST failure", "FRB2/Hang in POST failure", "FRB3/Processor startup/init failure",
+            "Configuration Error", "SM BIOS Uncorrectable CPU-complex Error", "Presence detected", "Disabled",
+            "Terminator presence detected", "Throttled", "Uncorrectable machine check exception", "Correctable machine check error",
+        ],
+        0x08 => &[
+            "Presence detected", "Failure detected", "Predictive failure", "Power Supply AC lost",
+            "AC lost or out-of-range", "AC out-of-range, but present", "Config Error",
+        ],
+        0x09 => &[
+            "Power off/down", "Power cycle", "240VA power down", "Interlock power down", "AC lost",
+            "Soft-power control failure", "Failure detected", "Predictive failure",
+        ],
+        0x0c => &[
+            "Correctable ECC", "Uncorrectable ECC", "Parity", "Memory Scrub Failed", "Memory Device Disabled",
+            "Correctable ECC logging limit reached", "Presence Detected", "Configuration Error", "Spare", "Throttled",
+            "Critical Overtemperature",
+        ],
+        0x0d => &[
+            "Drive Present", "Drive Fault", "Predictive Failure", "Hot Spare", "Parity Check In Progress",
+            "In Critical Array", "In Failed Array", "Rebuild In Progress", "Rebuild Aborted",
+        ],
+        0x0f => &["System Firmware Error", "System Firmware Hang", "System Firmware Progress"],
+        0x10 => &[
+            "Correctable memory error logging disabled", "Event logging disabled", "Log area reset/cleared",
+            "All event logging disabled", "Log full", "Log almost full",
+        ],
+        0x11 => &[
+            "BIOS Reset", "OS Reset", "OS Shut Down", "OS Power Down", "OS Power Cycle", "OS NMI/Diag Interrupt",
+            "OS Expired", "OS pre-timeout Interrupt",
+        ],
+        0x12 => &[
+            "System Reconfigured", "OEM System boot event", "Undetermined system hardware failure",
+            "Entry added to auxiliary log", "PEF Action", "Timestamp Clock Sync",
+        ],
+        0x13 => &[
+            "NMI/Diag Interrupt", "Bus Timeout", "I/O Channel check NMI", "Software NMI", "PCI PERR", "PCI SERR",
+            "EISA failsafe timeout", "Bus Correctable error", "Bus Uncorrectable error", "Fatal NMI", "Bus Fatal Error",
+            "Bus Degraded",
+        ],
+        0x14 => &["Power Button pressed", "Sleep Button pressed", "Reset Button pressed", "FRU Latch", "FRU Service"],
+        0x19 => &["Soft Power Control Failure", "Thermal Trip"],
+        0x1b => &["Connected", "Config Error"],
+        0x1d => &[
+            "Initiated by power up", "Initiated by hard reset", "Initiated by warm reset", "User requested PXE boot",
+            "Automatic boot to diagnostic", "OS initiated hard reset", "OS initiated warm reset", "System Restart",
+        ],
+        0x1e => &[
+            "No bootable media", "Non-bootable disk in drive", "PXE server not found", "Invalid boot sector",
+            "Timeout waiting for selection",
+        ],
+        0x1f => &[
+            "A: boot completed", "C: boot completed", "PXE boot completed", "Diagnostic boot completed",
+            "CD-ROM boot completed", "ROM boot completed", "boot completed - device not specified",
+            "Installation started", "Installation completed", "Installation aborted", "Installation failed",
+        ],
+        0x20 => &[
+            "Error during system startup", "Run-time critical stop", "OS graceful stop", "OS graceful shutdown",
+            "PEF initiated soft shutdown", "Agent not responding",
+        ],
+        0x21 => &[
+            "Fault Status", "Identify Status", "Device Installed", "Ready for Device Installation",
+            "Ready for Device Removal", "Slot Power is Off", "Device Removal Request", "Interlock", "Slot is Disabled",
+            "Spare Device",
+        ],
+        0x22 => &[
+            "S0/G0: working", "S1: sleeping with system hw & processor context maintained",
+            "S2: sleeping, processor context lost", "S3: sleeping, processor & hw context lost, memory retained",
+            "S4: non-volatile sleep/suspend-to-disk", "S5/G2: soft-off", "S4/S5: soft-off", "G3: mechanical off",
+            "Sleeping in S1/S2/S3 state", "G1: sleeping", "S5: entered by override", "Legacy ON state",
+            "Legacy OFF state", "Unknown",
+        ],
+        0x23 => &[
+            "Timer expired", "Hard reset", "Power down", "Power cycle", "reserved", "reserved", "reserved", "reserved",
+            "Timer interrupt",
+        ],
+        0x24 => &[
+            "Platform generated page", "Platform generated LAN alert", "Platform Event Trap generated",
+            "Platform generated SNMP trap, OEM format",
+        ],
+        0x25 => &["Present", "Absent", "Disabled"],
+        0x27 => &["Heartbeat Lost", "Heartbeat"],
+        0x28 => &[
+            "Sensor access degraded or unavailable", "Controller access degraded or unavailable",
+            "Management controller off-line", "Management controller unavailable", "Sensor failure", "FRU failure",
+        ],
+        0x29 => &["Low", "Failed", "Presence Detected"],
+        0x2a => &["Session Activated", "Session Deactivated", "Invalid Username or Password", "Invalid password disable"],
+        0x2b => &[
+            "Hardware change detected", "Firmware or software change detected", "Hardware incompatibility detected",
+            "Firmware or software incompatibility detected", "Invalid or unsupported hardware version",
+            "Invalid or unsupported firmware or software version", "Hardware change success",
+            "Firmware or software change success",
+        ],
+        0x2c => &[
+            "Not Installed", "Inactive", "Activation Requested", "Activation in Progress", "Active",
+            "Deactivation Requested", "Deactivation in Progress", "Communication lost",
+        ],
+        _ => &[],
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn timestamp(data: &[u8]) -> Option<i64> {
+    match u32::from_le_bytes([data[0], data[1], data[2], data[3]]) {
+        t if t <= TIMESTAMP_PRE_INIT || t == TIMESTAMP_UNSPECIFIED => None,
+        t => Some(t as i64),
+    }
+}
+
+/// The name of a sensor type, `None` for the reserved and OEM types
+pub fn sensor_type_name(code: u8) -> Option<&'static str> {
+    (code as usize).checked_sub(1).and_then(|i| SENSOR_TYPES.get(i)).copied()
+}
+
+/// The description of an event by the event/reading type, sensor type and event data
+pub fn describe(event_type: u8, sensor_type: u8, data: [u8; 3]) -> String {
+    let offset = (data[0] & 0x0f) as usize;
+    let known = match event_type {
+        0x01 => THRESHOLD.get(offset),
+        0x02..=0x0c => generic(event_type).get(offset),
+        0x6f => specific(sensor_type).get(offset),
+        _ => None,
+    };
+    let mut description = match (known, event_type) {
+        (Some(d), _) => String::from(*d),
+        (None, 0x70..=0x7f) => format!("OEM event type {:02x}h offset {:02x}h", event_type, offset),
+        (None, _) => format!("Unknown event type {:02x}h offset {:02x}h", event_type, offset),
+    };
+    // threshold events may carry the trigger reading and threshold, as raw values without the SDR
+    if event_type == 0x01 && data[0] & 0xc0 == 0x40 && data[0] & 0x30 == 0x10 {
+        description.push_str(&format!(" (Reading {:02x}h Threshold {:02x}h)", data[1], data[2]));
+    }
+    description
+}
+
+/// Decode a 16 bytes SEL record
+pub fn decode(record: &[u8; 16]) -> Event {
+    let id = u16::from_le_bytes([record[0], record[1]]);
+    let event = |time, sensor_type: String, sensor: String, description: String, asserted| Event {
+        id, time, severity: sel::severity(&description, asserted), sensor_type, sensor, description, asserted,
+    };
+    match record[2] {
+        0x02 => {
+            let sensor_type = sensor_type_name(record[10]).map_or(format!("{:02x}h", record[10]), String::from);
+            let asserted = record[12] & 0x80 == 0;
+            let description = describe(record[12] & 0x7f, record[10], [record[13], record[14], record[15]]);
+            event(timestamp(&record[3..7]), sensor_type, format!("#0x{:02x}", record[11]), description, asserted)
+        },
+        0xc0..=0xdf => {
+            let manufacturer = u32::from_le_bytes([record[7], record[8], record[9], 0]);
+            let description = format!("Manufacturer {:06x}h data {}", manufacturer, hex(&record[10..]));
+            event(timestamp(&record[3..7]), String::from("OEM"), format!("{:02x}", record[2]), description, true)
+        },
+        0xe0..=0xff => {
+            event(None, String::from("OEM"), format!("{:02x}", record[2]), format!("data {}", hex(&record[3..])), true)
+        },
+        t => event(None, String::new(), format!("{:02x}", t), format!("Unknown record type {:02x}h data {}", t, hex(&record[3..])), true),
+    }
+}
+
+/// Decode a 16 bytes SEL record, with the sensor named by its SDR record if found
+pub fn decode_named(record: &[u8; 16], sdrs: &[sdr::Record]) -> Event {
+    let mut event = decode(record);
+    if record[2] == 0x02 {
+        if let Some(name) = sdr::sensor_name(sdrs, record[7], record[11]) {
+            event.sensor = String::from(name);
+        }
+    }
+    event
+}
+
+/// The records of a dump, either binary as `sel writeraw` saves it, or text of hex bytes as
+/// `raw 0x0a 0x43` prints them, with the next record id and wrapped every 16 bytes, or one record per line
+pub fn records(dump: &[u8]) -> Result<Vec<[u8; 16]>, String> {
+    let text = std::str::from_utf8(dump).ok().filter(|t| t.chars().all(|c| c.is_ascii_hexdigit() || c.is_ascii_whitespace() || c == 'x' || c == 'X'));
+    let bytes: Vec<u8> = match text {
+        Some(text) if !text.trim().is_empty() => {
+            let mut bytes = Vec::new();
+            let mut response = Vec::new();
+            for line in text.lines() {
+                let line = line.split_whitespace()
+                    .map(|b| u8::from_str_radix(b.trim_start_matches("0x").trim_start_matches("0X"), 16).map_err(|e| format!("invalid byte {}: {}", b, e)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                // a response goes on over the full lines of 16 bytes, up to a shorter or longer one
+                let end = line.len() != 16;
+                response.extend(line);
+                if end {
+                    if response.len() == 18 {
+                        response.drain(..2);
+                    }
+                    bytes.append(&mut response);
+                }
+            }
+            bytes.append(&mut response);
+            bytes
+        },
+        _ => dump.to_vec(),
+    };
+    if !bytes.len().is_multiple_of(16) {
+        return Err(format!("{} bytes is not a multiple of the 16 bytes SEL record", bytes.len()));
+    }
+    Ok(bytes.chunks(16).map(|c| { let mut r = [0u8; 16]; r.copy_from_slice(c); r }).collect())
+}
+
+/// Read all the SEL records of the host by Get SEL Entry, one raw command per record
+pub fn fetch(host: &Login) -> Result<Vec<[u8; 16]>, String> {
+    let mut records = Vec::new();
+    let mut id: u16 = 0x0000;
+    loop {
+        let [lo, hi] = id.to_le_bytes();
+        let output = host.run(&["raw", "0x0a", "0x43", "0x00", "0x00", &format!("0x{:02x}", lo), &format!("0x{:02x}", hi), "0x00", "0xff"])?;
+        let bytes = output.split_whitespace().map(|b| u8::from_str_radix(b, 16)).collect::<Result<Vec<u8>, _>>().map_err(|e| e.to_string())?;
+        if bytes.len() != 18 {
+            return Err(format!("unexpected Get SEL Entry response: {}", output.trim()));
+        }
+        let mut record = [0u8; 16];
+        record.copy_from_slice(&bytes[2..]);
+        records.push(record);
+
+        id = u16::from_le_bytes([bytes[0], bytes[1]]);
+        if id == 0xffff || records.len() > 0xffff {
+            break;
+        }
+    }
+    Ok(records)
+}
+
+#[test]
+fn sel_record_decode() {
+    use crate::sel::Severity;
+
+    // threshold, upper critical going high with the trigger reading and threshold
+    let e = decode(&[0x04, 0x00, 0x02, 0x00, 0x2c, 0xd5, 0x5e, 0x20, 0x00, 0x04, 0x01, 0x30, 0x01, 0x59, 0x64, 0x63]);
+    assert_eq!((e.id, e.time, e.sensor_type.as_str(), e.sensor.as_str()), (4, Some(0x5ed52c00), "Temperature", "#0x30"));
+    assert_eq!((e.description.as_str(), e.asserted, e.severity), ("Upper Critical going high (Reading 64h Threshold 63h)", true, Severity::Critical));
+
+    // sensor-specific, deasserted
+    let e = decode(&[0x03, 0x00, 0x02, 0x00, 0x2c, 0xd5, 0x5e, 0x20, 0x00, 0x04, 0x08, 0x51, 0xef, 0x01, 0xff, 0xff]);
+    assert_eq!((e.sensor_type.as_str(), e.description.as_str(), e.asserted, e.severity), ("Power Supply", "Failure detected", false, Severity::Info));
+
+    // sensor-specific memory, before the clock was set
+    let e = decode(&[0x06, 0x00, 0x02, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x04, 0x0c, 0x87, 0x6f, 0xa0, 0x00, 0x03]);
+    assert_eq!((e.time, e.sensor_type.as_str(), e.description.as_str(), e.severity), (None, "Memory", "Correctable ECC", Severity::Warning));
+
+    // generic discrete redundancy
+    let e = decode(&[0x1c, 0x00, 0x02, 0x00, 0x2c, 0xd5, 0x5e, 0x20, 0x00, 0x04, 0x08, 0x60, 0x0b, 0x01, 0xff, 0xff]);
+    assert_eq!((e.description.as_str(), e.severity), ("Redundancy Lost", Severity::Critical));
+
+    // OEM types and events
+    let e = decode(&[0x1e, 0x00, 0x02, 0x00, 0x2c, 0xd5, 0x5e, 0x20, 0x00, 0x04, 0xc1, 0x01, 0x70, 0x02, 0xff, 0xff]);
+    assert_eq!((e.sensor_type.as_str(), e.description.as_str()), ("c1h", "OEM event type 70h offset 02h"));
+    let e = decode(&[0x1f, 0x00, 0xc0, 0x00, 0x2c, 0xd5, 0x5e, 0xa2, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    assert_eq!((e.time, e.sensor_type.as_str(), e.sensor.as_str(), e.description.as_str()), (Some(0x5ed52c00), "OEM", "c0", "Manufacturer 0002a2h data 010203040506"));
+    let e = decode(&[0x20, 0x00, 0xe0, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d]);
+    assert_eq!((e.time, e.description.as_str()), (None, "data 0102030405060708090a0b0c0d"));
+}
+
+#[test]
+fn sel_record_named() {
+    let records = records(&std::fs::read("tests/fixtures/sel_dump.bin").unwrap()).unwrap();
+    let sdrs = sdr::parse(&std::fs::read("tests/fixtures/sdr_dump.bin").unwrap()).unwrap();
+    let sensors: Vec<String> = records.iter().map(|r| decode_named(r, &sdrs).sensor).collect();
+    assert_eq!(sensors, vec!["#0x07", "PS1 Status", "CPU1 Temp", "#0x83"]);
+}
+
+#[test]
+fn sel_record_dump() {
+    let dump = include_bytes!("../tests/fixtures/sel_dump.bin");
+    let events: Vec<Event> = records(dump).unwrap().iter().map(decode).collect();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events.iter().map(|e| e.description.as_str()).collect::<Vec<_>>(), vec![
+        "Log area reset/cleared", "Failure detected", "Upper Critical going high (Reading 64h Threshold 63h)", "Timestamp Clock Sync",
+    ]);
+
+    // the same records as `raw 0x0a 0x43` prints them, with the next record id
+    let text: String = records(dump).unwrap().iter().enumerate()
+        .map(|(i, r)| format!(" {:02x} 00 {}\n", i + 2, r.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")))
+        .collect();
+    assert_eq!(records(text.as_bytes()).unwrap().iter().map(decode).collect::<Vec<_>>(), events);
+
+    // as ipmitool prints them, wrapped after 16 bytes
+    let raw = std::fs::read("tests/fixtures/sel_raw_supermicro.txt").unwrap();
+    assert_eq!(records(&raw).unwrap().iter().map(decode).collect::<Vec<_>>(), events);
+
+    assert!(records(&dump[..20]).is_err());
+}
@@ -0,0 +1,619 @@
+/*++ @file
+
+  Copyright ©2020 Liu Yi, liuyi28@lenovo.com
+
+  This program is just made available under the terms and conditions of the
+  MIT license: http://www.efikarl.com/mit-license.html
+
+  THE PROGRAM IS DISTRIBUTED UNDER THE MIT LICENSE ON AN "AS IS" BASIS,
+  WITHOUT WARRANTIES OR REPRESENTATIONS OF ANY KIND, EITHER EXPRESS OR IMPLIED.
+--*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::{Format, Group, Host, Login};
+
+#[derive(StructOpt, Debug)]
+pub struct SensorsOpts {
+    /// Only the sensors of the type: temperature, fan, voltage, current, power, discrete or other
+    #[structopt(short = "T", long = "type")]
+    kind: Option<String>,
+    /// Only the sensors with the name matching the glob, e.g. "CPU*"
+    #[structopt(short, long)]
+    name: Option<String>,
+    /// Output format: table, json or csv
+    #[structopt(short, long, default_value = "table")]
+    format: Format,
+    /// Refresh the readings in place every <watch> seconds
+    #[structopt(short, long)]
+    watch: Option<u64>,
+    /// Append every sample of the watch to the CSV file
+    #[structopt(short, long, requires = "watch")]
+    output: Option<path::PathBuf>,
+    #[structopt(flatten)]
+    group: Group,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum SensorCommand {
+    /// Poll the sensors of the host(s) and store the readings
+    Record {
+        /// Seconds between the samples
+        #[structopt(short, long, default_value = "60")]
+        interval: u64,
+        /// Take one sample and exit, e.g. when run by a scheduler
+        #[structopt(long)]
+        once: bool,
+        #[structopt(flatten)]
+        group: Group,
+    },
+    /// Print the stored readings of the sensor(s) matching the glob
+    History {
+        sensor: String,
+        /// Only the readings of the last <since>, e.g. 30m, 24h or 7d
+        #[structopt(short, long, default_value = "24h", parse(try_from_str = crate::seconds))]
+        since: u64,
+        /// Output format: table, json or csv
+        #[structopt(short, long, default_value = "table")]
+        format: Format,
+        /// Only the readings of the hosts with the tag, default all hosts
+        #[structopt(short, long)]
+        tag: Option<String>,
+    },
+    /// Show or set how long the stored readings are kept and when they are downsampled
+    Retention {
+        /// Delete the readings older than <keep>, e.g. 90d
+        #[structopt(long, parse(try_from_str = crate::seconds))]
+        keep: Option<u64>,
+        /// Downsample the readings older than <downsample-after>, e.g. 7d
+        #[structopt(long, parse(try_from_str = crate::seconds))]
+        downsample_after: Option<u64>,
+        /// Downsample to one reading (avg, min and max) per <downsample-to>, e.g. 1h
+        #[structopt(long, parse(try_from_str = crate::seconds))]
+        downsample_to: Option<u64>,
+    },
+}
+
+/// A reading of `ipmitool sensor list`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Sensor {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// `None` for discrete sensors and sensors without a reading
+    pub value: Option<f64>,
+    pub unit: String,
+    /// ok, nc, cr, nr, na, or the state bits of a discrete sensor, e.g. 0x0100
+    pub status: String,
+    pub lower_non_recoverable: Option<f64>,
+    pub lower_critical: Option<f64>,
+    pub lower_non_critical: Option<f64>,
+    pub upper_non_critical: Option<f64>,
+    pub upper_critical: Option<f64>,
+    pub upper_non_recoverable: Option<f64>,
+}
+
+/// The sensor type by the reading unit, `sensor list` has no type column
+fn kind(unit: &str) -> &'static str {
+    match unit.to_lowercase().as_str() {
+        "degrees c" | "degrees f" => "temperature",
+        "rpm"                     => "fan",
+        "volts"                   => "voltage",
+        "amps"                    => "current",
+        "watts"                   => "power",
+        "discrete"                => "discrete",
+        _                         => "other",
+    }
+}
+
+fn number(field: Option<&&str>) -> Option<f64> {
+    field.and_then(|f| if f.starts_with("0x") { None } else { f.parse::<f64>().ok() })
+}
+
+/// Parse `ipmitool sensor list`, lines which are not a sensor are skipped
+pub fn parse(output: &str) -> Vec<Sensor> {
+    let mut sensors = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split('|').map(|f| f.trim()).collect();
+        if fields.len() < 4 || fields[0].is_empty() {
+            continue;
+        }
+        let unit = fields[2];
+        sensors.push(Sensor {
+            name: String::from(fields[0]),
+            kind: kind(unit),
+            value: number(fields.get(1)),
+            unit: String::from(unit),
+            status: String::from(fields[3]),
+            lower_non_recoverable: number(fields.get(4)),
+            lower_critical: number(fields.get(5)),
+            lower_non_critical: number(fields.get(6)),
+            upper_non_critical: number(fields.get(7)),
+            upper_critical: number(fields.get(8)),
+            upper_non_recoverable: number(fields.get(9)),
+        });
+    }
+    sensors
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl Sensor {
+    /// The level by the BMC status, or by the thresholds crossed if the BMC does not tell
+    pub fn level(&self) -> Level {
+        let by_status = match self.status.as_str() {
+            "nc"        => Level::Warning,
+            "cr" | "nr" => Level::Critical,
+            _           => Level::Ok,
+        };
+        let by_value = match self.value {
+            Some(v) => {
+                let below = |t: Option<f64>| t.is_some_and(|t| v <= t);
+                let above = |t: Option<f64>| t.is_some_and(|t| v >= t);
+                if below(self.lower_critical) || below(self.lower_non_recoverable) || above(self.upper_critical) || above(self.upper_non_recoverable) {
+                    Level::Critical
+                } else if below(self.lower_non_critical) || above(self.upper_non_critical) {
+                    Level::Warning
+                } else {
+                    Level::Ok
+                }
+            },
+            None => Level::Ok,
+        };
+        if by_status > by_value { by_status } else { by_value }
+    }
+    pub fn matches(&self, kind: Option<&str>, name: Option<&str>) -> bool {
+        kind.is_none_or(|k| k.eq_ignore_ascii_case(self.kind)) && name.is_none_or(|n| crate::glob(n, &self.name))
+    }
+}
+
+fn na(v: Option<f64>) -> String {
+    v.map_or(String::from("na"), |v| format!("{:.3}", v))
+}
+
+fn print_table(sensors: &[(i64, &Login, Sensor)]) {
+    println!("{:<16}  {:>10}  {:<10}  {:<6}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}", "Name", "Value", "Unit", "Status", "LNR", "LCR", "LNC", "UNC", "UCR", "UNR");
+    let mut ip = "";
+    for (_, host, s) in sensors {
+        if host.ip != ip {
+            ip = &host.ip;
+            println!("---- {} ----", ip);
+        }
+        let value = match s.value {
+            Some(v) => format!("{:.3}", v),
+            None => String::from("na"),
+        };
+        println!(
+            "{:<16}  {:>10}  {:<10}  {:<6}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}",
+            s.name, value, s.unit, s.status,
+            na(s.lower_non_recoverable), na(s.lower_critical), na(s.lower_non_critical),
+            na(s.upper_non_critical), na(s.upper_critical), na(s.upper_non_recoverable),
+        );
+    }
+}
+
+fn print_csv(sensors: &[(i64, &Login, Sensor)]) {
+    println!("host,name,type,value,unit,status,lower_non_recoverable,lower_critical,lower_non_critical,upper_non_critical,upper_critical,upper_non_recoverable");
+    let f = |v: Option<f64>| v.map_or(String::new(), |v| v.to_string());
+    for (_, host, s) in sensors {
+        println!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            crate::csv(&host.ip), crate::csv(&s.name), s.kind, f(s.value), crate::csv(&s.unit), crate::csv(&s.status),
+            f(s.lower_non_recoverable), f(s.lower_critical), f(s.lower_non_critical),
+            f(s.upper_non_critical), f(s.upper_critical), f(s.upper_non_recoverable),
+        );
+    }
+}
+
+fn print_json(sensors: &[(i64, &Login, Sensor)]) {
+    #[derive(Serialize)]
+    struct Reading<'a> {
+        host: &'a str,
+        #[serde(flatten)]
+        sensor: &'a Sensor,
+    }
+    let readings: Vec<Reading> = sensors.iter().map(|(_, host, sensor)| Reading { host: &host.ip, sensor }).collect();
+    println!("{}", serde_json::to_string_pretty(&readings).unwrap());
+}
+
+type Readings<'a> = Vec<(i64, &'a Login, Sensor)>;
+
+/// Read the sensors of the hosts, the failures are returned as (host, error)
+fn read<'a>(hosts: &'a [(i64, Login)], jobs: usize, kind: Option<&str>, name: Option<&str>) -> (Readings<'a>, Vec<(&'a Login, String)>) {
+    let results = crate::parallel(hosts.iter().collect(), jobs, |(_, host)| host.run(&["sensor", "list"]));
+    let mut sensors = Vec::new();
+    let mut failures = Vec::new();
+    for ((id, host), result) in hosts.iter().zip(results) {
+        match result {
+            Ok(output) => {
+                for s in parse(&output) {
+                    if s.matches(kind, name) {
+                        sensors.push((*id, host, s));
+                    }
+                }
+            },
+            Err(e) => failures.push((host, e)),
+        }
+    }
+    (sensors, failures)
+}
+
+fn paint(text: &str, level: Level, changed: bool) -> String {
+    let color = match level {
+        Level::Critical => "\x1b[1;31m",
+        Level::Warning  => "\x1b[1;33m",
+        Level::Ok if changed => "\x1b[1;36m",
+        Level::Ok => return String::from(text),
+    };
+    format!("{}{}\x1b[0m", color, text)
+}
+
+fn watch(hosts: &[(i64, Login)], opts: &SensorsOpts, interval: u64) {
+    let mut output = opts.output.as_ref().map(|file| {
+        let new = !file.is_file();
+        let mut f = fs::OpenOptions::new().create(true).append(true).open(file).unwrap();
+        if new {
+            writeln!(f, "time,host,name,type,value,unit,status").unwrap();
+        }
+        f
+    });
+
+    // (last value, min, max) by (host, sensor) during the watch
+    let mut seen: HashMap<(String, String), (Option<f64>, f64, f64)> = HashMap::new();
+    let start = Instant::now();
+    for sample in 1.. {
+        let time = crate::rfc3339(crate::now());
+        let (sensors, failures) = read(hosts, opts.group.jobs, opts.kind.as_deref(), opts.name.as_deref());
+
+        let mut screen = format!("\x1b[H\x1b[2JEvery {}s, sample {}, {}s since start\n\n", interval, sample, start.elapsed().as_secs());
+        screen.push_str(&format!("{:<16}  {:>10}  {:<10}  {:<6}  {:>10}  {:>10}  {:>9}  {:>9}  {:>9}  {:>9}\n", "Name", "Value", "Unit", "Status", "Min", "Max", "LCR", "LNC", "UNC", "UCR"));
+        let mut ip = "";
+        for (_, host, s) in &sensors {
+            if host.ip != ip {
+                ip = &host.ip;
+                screen.push_str(&format!("---- {} ----\n", ip));
+            }
+            let key = (host.ip.clone(), s.name.clone());
+            let (changed, min, max) = match (seen.get(&key), s.value) {
+                (Some(&(last, min, max)), Some(v)) => (sample > 1 && last != Some(v), min.min(v), max.max(v)),
+                (Some(&(last, min, max)), None) => (last.is_some(), min, max),
+                (None, Some(v)) => (false, v, v),
+                (None, None) => (false, f64::NAN, f64::NAN),
+            };
+            seen.insert(key, (s.value, min, max));
+
+            let value = format!("{:>10}", na(s.value));
+            let min_max = |v: f64| if v.is_nan() { String::from("na") } else { format!("{:.3}", v) };
+            screen.push_str(&format!(
+                "{:<16}  {}  {:<10}  {:<6}  {:>10}  {:>10}  {:>9}  {:>9}  {:>9}  {:>9}\n",
+                s.name, paint(&value, s.level(), changed), s.unit, s.status, min_max(min), min_max(max),
+                na(s.lower_critical), na(s.lower_non_critical), na(s.upper_non_critical), na(s.upper_critical),
+            ));
+
+            if let Some(f) = output.as_mut() {
+                writeln!(
+                    f, "{},{},{},{},{},{},{}",
+                    time, crate::csv(&host.ip), crate::csv(&s.name), s.kind, s.value.map_or(String::new(), |v| v.to_string()), crate::csv(&s.unit), crate::csv(&s.status),
+                ).unwrap();
+            }
+        }
+        for (host, e) in &failures {
+            screen.push_str(&format!("---- {} ----\nfailed: {}\n", host.ip, e));
+        }
+        print!("{}", screen);
+        std::io::stdout().flush().unwrap();
+
+        std::thread::sleep(Duration::from_secs(interval.max(1)));
+    }
+}
+
+/// Retention settings in seconds: (keep, downsample after, downsample to)
+fn retention(connection: &sqlite::Connection) -> (u64, u64, u64) {
+    let get = |key, default| crate::setting(connection, key).and_then(|v| v.parse::<u64>().ok()).unwrap_or(default);
+    (get("sensor.keep", 90 * 86400), get("sensor.downsample_after", 7 * 86400), get("sensor.downsample_to", 3600))
+}
+
+/// Delete the expired readings and downsample the old ones
+fn retain(connection: &sqlite::Connection, now: i64) {
+    let (keep, after, to) = retention(connection);
+    let to = to.max(1) as i64;
+    // align to the bucket so that a bucket is always downsampled as a whole
+    let cutoff = (now - after as i64) / to * to;
+    connection.execute(format!(
+        "
+        BEGIN;
+        DELETE FROM sensor_samples WHERE time < {expired};
+        INSERT INTO sensor_samples (host_id, time, name, type, unit, value, min, max, status, span)
+            SELECT host_id, time / {to} * {to}, name, type, unit, AVG(value), MIN(min), MAX(max),
+                CASE WHEN SUM(status IN ('cr', 'nr')) > 0 THEN 'cr' WHEN SUM(status = 'nc') > 0 THEN 'nc' ELSE MAX(status) END, {to}
+            FROM sensor_samples WHERE time < {cutoff} AND span < {to} GROUP BY host_id, name, time / {to};
+        DELETE FROM sensor_samples WHERE time < {cutoff} AND span < {to};
+        COMMIT;
+        ",
+        expired = now - keep as i64, to = to, cutoff = cutoff,
+    )).unwrap();
+}
+
+fn record(connection: &sqlite::Connection, hosts: &[(i64, Login)], jobs: usize, interval: u64, once: bool) {
+    loop {
+        let time = crate::now();
+        let (sensors, failures) = read(hosts, jobs, None, None);
+
+        connection.execute("BEGIN").unwrap();
+        let mut statement = connection.prepare(
+            "INSERT INTO sensor_samples (host_id, time, name, type, unit, value, min, max, status) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        ).unwrap();
+        for (id, _, s) in &sensors {
+            statement.reset().unwrap();
+            statement.bind(1, *id).unwrap();
+            statement.bind(2, time).unwrap();
+            statement.bind(3, s.name.as_str()).unwrap();
+            statement.bind(4, s.kind).unwrap();
+            statement.bind(5, s.unit.as_str()).unwrap();
+            for i in 6..=8 {
+                match s.value {
+                    Some(v) => statement.bind(i, v).unwrap(),
+                    None => statement.bind(i, ()).unwrap(),
+                }
+            }
+            statement.bind(9, s.status.as_str()).unwrap();
+            statement.next().unwrap();
+        }
+        connection.execute("COMMIT").unwrap();
+        retain(connection, time);
+
+        println!("[{}] {} readings of {} hosts stored", crate::datetime(time), sensors.len(), hosts.len() - failures.len());
+        for (host, e) in &failures {
+            println!("[{}] {:<15} failed: {}", crate::datetime(time), host.ip, e);
+        }
+        if once {
+            break;
+        }
+        std::thread::sleep(Duration::from_secs(interval.max(1)));
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Sample {
+    time: String,
+    host: String,
+    name: String,
+    unit: String,
+    value: Option<f64>,
+    min: Option<f64>,
+    max: Option<f64>,
+    status: String,
+    /// Seconds the sample covers, 0 for a reading as is
+    span: i64,
+}
+
+fn history(connection: &sqlite::Connection, sensor: &str, from: i64, tag: Option<&str>, format: Format) -> Vec<Sample> {
+    let mut statement = connection.prepare(
+        "
+        SELECT s.time, h.ip, s.name, s.unit, s.value, s.min, s.max, s.status, s.span FROM sensor_samples s JOIN hosts h ON h.id = s.host_id
+        WHERE s.time >= ? AND (? IS NULL OR s.host_id IN (SELECT host_id FROM tags WHERE tag = ?))
+        ORDER BY h.id, s.name, s.time
+        "
+    ).unwrap();
+    statement.bind(1, from).unwrap();
+    match tag {
+        Some(tag) => {
+            statement.bind(2, tag).unwrap();
+            statement.bind(3, tag).unwrap();
+        },
+        None => {
+            statement.bind(2, ()).unwrap();
+            statement.bind(3, ()).unwrap();
+        },
+    }
+    let mut samples = Vec::new();
+    while let sqlite::State::Row = statement.next().unwrap() {
+        let name = statement.read::<String>(2).unwrap();
+        if !crate::glob(sensor, &name) {
+            continue;
+        }
+        let time = statement.read::<i64>(0).unwrap();
+        let real = |i| match statement.kind(i) {
+            sqlite::Type::Null => None,
+            _ => Some(statement.read::<f64>(i).unwrap()),
+        };
+        samples.push(Sample {
+            time: if format == Format::Table { crate::datetime(time) } else { crate::rfc3339(time) },
+            host: statement.read::<String>(1).unwrap(),
+            name,
+            unit: statement.read::<String>(3).unwrap(),
+            value: real(4),
+            min: real(5),
+            max: real(6),
+            status: statement.read::<String>(7).unwrap(),
+            span: statement.read::<i64>(8).unwrap(),
+        });
+    }
+    samples
+}
+
+fn print_history(samples: &[Sample], format: Format) {
+    let f = |v: Option<f64>| v.map_or(String::new(), |v| v.to_string());
+    match format {
+        Format::Table => {
+            println!("{:<19}  {:<15}  {:<16}  {:>10}  {:>10}  {:>10}  {:<10}  {:<6}", "Time", "Host", "Name", "Value", "Min", "Max", "Unit", "Status");
+            for s in samples {
+                println!("{:<19}  {:<15}  {:<16}  {:>10}  {:>10}  {:>10}  {:<10}  {:<6}", s.time, s.host, s.name, na(s.value), na(s.min), na(s.max), s.unit, s.status);
+            }
+        },
+        Format::Json => println!("{}", serde_json::to_string_pretty(samples).unwrap()),
+        Format::Csv => {
+            println!("time,host,name,value,min,max,unit,status,span");
+            for s in samples {
+                println!(
+                    "{},{},{},{},{},{},{},{},{}",
+                    s.time, crate::csv(&s.host), crate::csv(&s.name), f(s.value), f(s.min), f(s.max), crate::csv(&s.unit), crate::csv(&s.status), s.span,
+                );
+            }
+        },
+    }
+}
+
+pub fn run(connection: &sqlite::Connection, cmd: Option<SensorCommand>, opts: SensorsOpts) {
+    match cmd {
+        Some(SensorCommand::Record{interval, once, group}) => {
+            let hosts = Host::group(connection, group.tag.as_deref());
+            if hosts.is_empty() {
+                println!("Please set default host or tag hosts with command:");
+                println!("    ipmi.exe host use <index of host>");
+                println!("    ipmi.exe host tag <index of host> <tag>");
+                return;
+            }
+            record(connection, &hosts, group.jobs, interval, once);
+            return;
+        },
+        Some(SensorCommand::History{sensor, since, format, tag}) => {
+            print_history(&history(connection, &sensor, crate::now() - since as i64, tag.as_deref(), format), format);
+            return;
+        },
+        Some(SensorCommand::Retention{keep, downsample_after, downsample_to}) => {
+            for (key, value) in [("sensor.keep", keep), ("sensor.downsample_after", downsample_after), ("sensor.downsample_to", downsample_to)] {
+                if let Some(value) = value {
+                    crate::set_setting(connection, key, &value.to_string());
+                }
+            }
+            let (keep, after, to) = retention(connection);
+            println!("keep:             {}s", keep);
+            println!("downsample after: {}s", after);
+            println!("downsample to:    {}s", to);
+            return;
+        },
+        None => {},
+    }
+
+    let hosts = Host::group(connection, opts.group.tag.as_deref());
+    if hosts.is_empty() {
+        println!("Please set default host or tag hosts with command:");
+        println!("    ipmi.exe host use <index of host>");
+        println!("    ipmi.exe host tag <index of host> <tag>");
+        return;
+    }
+
+    if let Some(interval) = opts.watch {
+        watch(&hosts, &opts, interval);
+        return;
+    }
+
+    let (sensors, failures) = read(&hosts, opts.group.jobs, opts.kind.as_deref(), opts.name.as_deref());
+    for (host, e) in failures {
+        eprintln!("{:<15} failed: {}", host.ip, e);
+    }
+    match opts.format {
+        Format::Table => print_table(&sensors),
+        Format::Json  => print_json(&sensors),
+        Format::Csv   => print_csv(&sensors),
+    }
+}
+
+#[test]
+fn sensor_parse() {
+    let sensors = parse(include_str!("../tests/fixtures/sensor_list_supermicro.txt"));
+    assert_eq!(sensors.len(), 8);
+    assert_eq!(sensors[0], Sensor {
+        name: String::from("CPU Temp"), kind: "temperature", value: Some(38.0), unit: String::from("degrees C"), status: String::from("ok"),
+        lower_non_recoverable: Some(0.0), lower_critical: Some(0.0), lower_non_critical: Some(0.0),
+        upper_non_critical: Some(94.0), upper_critical: Some(99.0), upper_non_recoverable: Some(99.0),
+    });
+    assert_eq!((sensors[3].name.as_str(), sensors[3].value, sensors[3].status.as_str()), ("FAN2", None, "na"));
+    assert_eq!((sensors[7].kind, sensors[7].value, sensors[7].status.as_str()), ("discrete", None, "0x0100"));
+
+    let sensors = parse(include_str!("../tests/fixtures/sensor_list_dell.txt"));
+    assert_eq!(sensors.len(), 9);
+    assert_eq!((sensors[2].lower_critical, sensors[2].lower_non_critical, sensors[2].upper_critical), (Some(-7.0), Some(3.0), Some(42.0)));
+    assert_eq!(sensors.iter().filter(|s| s.matches(Some("fan"), None)).count(), 2);
+    assert_eq!(sensors.iter().filter(|s| s.matches(None, Some("*temp"))).count(), 3);
+
+    let sensors = parse(include_str!("../tests/fixtures/sensor_list_lenovo.txt"));
+    assert_eq!(sensors.len(), 7);
+    assert_eq!((sensors[4].kind, sensors[4].value, sensors[4].status.as_str(), sensors[4].upper_non_critical), ("power", Some(160.0), "nc", Some(150.0)));
+
+    let sensors = parse(include_str!("../tests/fixtures/sensor_list_hpe.txt"));
+    assert_eq!(sensors.len(), 7);
+    assert_eq!((sensors[2].kind, sensors[2].unit.as_str()), ("other", "unspecified"));
+    assert_eq!((sensors[4].status.as_str(), sensors[4].upper_critical), ("cr", Some(100.0)));
+    // short lines of the older ipmitool have no thresholds
+    assert_eq!((sensors[6].value, sensors[6].upper_critical), (Some(232.0), None));
+}
+
+#[test]
+fn sensor_level() {
+    let sensors = parse(include_str!("../tests/fixtures/sensor_list_lenovo.txt"));
+    assert_eq!(sensors[0].level(), Level::Ok);
+    // by the BMC status
+    assert_eq!(sensors[4].level(), Level::Warning);
+
+    let mut sensor = sensors[0].clone();
+    sensor.value = Some(43.0);
+    assert_eq!(sensor.level(), Level::Warning);
+    sensor.value = Some(47.5);
+    assert_eq!(sensor.level(), Level::Critical);
+
+    let sensors = parse(include_str!("../tests/fixtures/sensor_list_hpe.txt"));
+    assert_eq!(sensors[4].level(), Level::Critical);
+    assert_eq!(sensors[0].level(), Level::Ok);
+}
+
+#[test]
+fn sensor_retain() {
+    let db_name = "sensor.db";
+
+    let database = {
+        let (_, database) = Host::init(Some(db_name));
+        database.clone()
+    };
+    fs::remove_file(&database).unwrap_or(());
+
+    { // case: expired readings deleted, old readings downsampled per hour
+        let (connection, _) = Host::init(Some(db_name));
+        connection.execute("INSERT INTO hosts VALUES (1, 0, '000.000.000.000', 'admin', 'admin')").unwrap();
+        let now = 100 * 86400;
+        for (time, value, status) in [(now - 91 * 86400, 20.0, "ok"), (now - 8 * 86400, 20.0, "ok"), (now - 8 * 86400 + 60, 30.0, "nc"), (now - 8 * 86400 + 120, 40.0, "ok"), (now - 60, 25.0, "ok")] {
+            connection.execute(format!(
+                "INSERT INTO sensor_samples (host_id, time, name, type, unit, value, min, max, status) VALUES (1, {0}, 'Temp', 'temperature', 'degrees C', {1}, {1}, {1}, '{2}')",
+                time, value, status,
+            )).unwrap();
+        }
+        retain(&connection, now);
+        retain(&connection, now);
+
+        let mut rows = Vec::new();
+        let mut statement = connection.prepare("SELECT time, value, min, max, status, span FROM sensor_samples ORDER BY time").unwrap();
+        while let sqlite::State::Row = statement.next().unwrap() {
+            rows.push((
+                statement.read::<i64>(0).unwrap(),
+                statement.read::<f64>(1).unwrap(),
+                statement.read::<f64>(2).unwrap(),
+                statement.read::<f64>(3).unwrap(),
+                statement.read::<String>(4).unwrap(),
+                statement.read::<i64>(5).unwrap(),
+            ));
+        }
+        assert_eq!(rows, vec![
+            (now - 8 * 86400, 30.0, 20.0, 40.0, String::from("nc"), 3600),
+            (now - 60, 25.0, 25.0, 25.0, String::from("ok"), 0),
+        ]);
+
+        let samples = history(&connection, "t*", now - 86400, None, Format::Csv);
+        assert_eq!(samples.len(), 1);
+        assert_eq!((samples[0].host.as_str(), samples[0].value, samples[0].span), ("000.000.000.000", Some(25.0), 0));
+        assert!(history(&connection, "fan*", now - 86400, None, Format::Csv).is_empty());
+        assert!(history(&connection, "t*", now - 86400, Some("lab"), Format::Csv).is_empty());
+    }
+    fs::remove_file(&database).unwrap();
+}